@@ -1,23 +1,161 @@
 let nativePort;
 let contentPortById = new Map();
+
+/**
+ * Tells the native host which page a tab is showing, so it can start
+ * from a snapshot of that page's index.
+ */
+function loadPage(contentPort) {
+    nativePort.postMessage({
+        LoadPage: {
+            tabId: contentPort.sender.tab.id,
+            url: contentPort.sender.url,
+        },
+    });
+}
+
+/**
+ * Tells the native host which tab is in front, so that Talon listens for
+ * its links and not those of tabs loading in the background.
+ */
+function focusTab(tabId) {
+    nativePort.postMessage({ FocusTab: { tabId } });
+}
+
+function focusActiveTab(query) {
+    chrome.tabs.query({ active: true, ...query }, (tabs) =>
+        tabs.forEach((tab) => focusTab(tab.id)),
+    );
+}
+
+chrome.tabs.onActivated.addListener(({ tabId }) => focusTab(tabId));
+chrome.windows.onFocusChanged.addListener((windowId) => {
+    if (windowId !== chrome.windows.WINDOW_ID_NONE) {
+        focusActiveTab({ windowId });
+    }
+});
+
+/**
+ * Says why an action couldn't be done, since nothing else would.
+ */
+function notifyFailure(what, error) {
+    chrome.notifications.create({
+        type: "basic",
+        title: "voicesurf",
+        message: `Couldn't ${what}: ${error.message}`,
+    });
+}
+
 chrome.runtime.onConnect.addListener((contentPort) => {
     const tabId = contentPort.sender.tab.id;
     contentPortById.set(tabId, contentPort);
+    loadPage(contentPort);
     contentPort.onMessage.addListener((data) => {
+        // Pages can't open background tabs, write to the clipboard or
+        // download from other sites themselves. The content script only asks
+        // for these for the link a query found, never because the page did.
+        if (data.OpenTab) {
+            chrome.tabs.create({
+                url: data.OpenTab.url,
+                active: false,
+                openerTabId: tabId,
+            });
+            return;
+        }
+        if (data.CopyHref) {
+            navigator.clipboard
+                .writeText(data.CopyHref.url)
+                .catch((error) => notifyFailure("copy the link", error));
+            return;
+        }
+        if (data.Download) {
+            chrome.downloads.download({ url: data.Download.url }, () => {
+                if (chrome.runtime.lastError) {
+                    notifyFailure("download the link", chrome.runtime.lastError);
+                }
+            });
+            return;
+        }
         if (data.UpdateIndex) {
             data.UpdateIndex.tabId = tabId;
         } else if (data.FocusTab) {
             data.FocusTab.tabId = tabId;
+        } else if (data.Clicked) {
+            data.Clicked.tabId = tabId;
         }
         nativePort.postMessage(data);
     });
-    contentPort.onDisconnect.addListener((contentPort) =>
-        contentPortById.delete(contentPort.sender.tab.id),
-    );
+    // The page went away, so the host can let go of its index. A page which
+    // replaced it in the same tab will have connected a new port.
+    contentPort.onDisconnect.addListener(() => {
+        if (contentPortById.get(tabId) === contentPort) {
+            closeTab(tabId);
+        }
+    });
 });
 
-nativePort = chrome.runtime.connectNative("voicesurf");
-nativePort.onMessage.addListener((response) => {
-    const tabId = response.tabId;
-    contentPortById.get(tabId)?.postMessage({ detail: response.best });
-});
+/**
+ * Tells the native host a tab is gone, so that "surf all" stops searching it.
+ */
+function closeTab(tabId) {
+    contentPortById.delete(tabId);
+    nativePort.postMessage({ CloseTab: { tabId } });
+}
+
+chrome.tabs.onRemoved.addListener((tabId) => closeTab(tabId));
+
+function connectNative() {
+    nativePort = chrome.runtime.connectNative("voicesurf");
+    nativePort.onMessage.addListener((response) => {
+        if (response.Results) {
+            const { tabId, classification, best, action } = response.Results;
+            contentPortById.get(tabId)?.postMessage({
+                detail: { classification, best, action },
+            });
+        } else if (response.Hints) {
+            const { tabId, hints } = response.Hints;
+            contentPortById.get(tabId)?.postMessage({ detail: { hints } });
+        } else if (response.CrossTabResults) {
+            const { classification, best, action } = response.CrossTabResults;
+            if (best.length === 0) {
+                return;
+            }
+            // Switch to the tab with the best result which is still open, and
+            // only consider the results in it.
+            const open = best.find(([resultTabId, _]) =>
+                contentPortById.has(resultTabId),
+            );
+            if (!open) {
+                return;
+            }
+            const tabId = open[0];
+            const contentPort = contentPortById.get(tabId);
+            chrome.tabs.update(tabId, { active: true });
+            chrome.windows.update(contentPort.sender.tab.windowId, {
+                focused: true,
+            });
+            contentPort.postMessage({
+                detail: {
+                    classification,
+                    best: best
+                        .filter(([resultTabId, _]) => resultTabId === tabId)
+                        .map(([_, elId]) => elId),
+                    action,
+                    anywhere: true,
+                },
+            });
+        }
+    });
+    // If the host restarts, the open pages won't send their whole index
+    // again, so it has to make do with their snapshots.
+    nativePort.onDisconnect.addListener(() =>
+        setTimeout(() => {
+            connectNative();
+            contentPortById.forEach(loadPage);
+            focusActiveTab({ lastFocusedWindow: true });
+        }, 1000),
+    );
+}
+
+connectNative();
+focusActiveTab({ lastFocusedWindow: true });
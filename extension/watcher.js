@@ -2,17 +2,77 @@
 const cloneIntoPolyfill = typeof cloneInto !== 'undefined' ? cloneInto : ((m, _) => m);
 
 const port = chrome.runtime.connect();
+
+// The page can fire these events too, so only pass on what it could have
+// told the native host by itself.
 document.addEventListener("voicesurf.browser", (message) => {
-    port.postMessage(message.detail);
+    const { UpdateIndex, Clicked } = message.detail || {};
+    if (UpdateIndex) {
+        port.postMessage({ UpdateIndex });
+    } else if (Clicked) {
+        port.postMessage({ Clicked });
+    }
+});
+
+/**
+ * Actions which need the extension's permissions: pages can't open
+ * background tabs, write to the clipboard without the user acting, or
+ * download from other sites themselves.
+ */
+const privilegedActions = new Set([
+    "OpenInBackgroundTab",
+    "MiddleClick",
+    "CopyHref",
+    "Download",
+]);
+
+// The results the page is handling, while it handles them. The page handles
+// them synchronously, so a privileged action asked for at any other time
+// didn't come from a query.
+let pendingResults;
+
+document.addEventListener("voicesurf.perform", (event) => {
+    const { id, action } = event.detail || {};
+    if (
+        !pendingResults ||
+        pendingResults.action !== action ||
+        !pendingResults.best.includes(id)
+    ) {
+        return;
+    }
+    // Each result is acted on once.
+    pendingResults = undefined;
+    // Read the address here rather than trusting the page to say what it is.
+    const url = event.target.href;
+    if (!url) {
+        return;
+    }
+    switch (action) {
+        case "CopyHref":
+            port.postMessage({ CopyHref: { url } });
+            break;
+        case "Download":
+            port.postMessage({ Download: { url } });
+            break;
+        default:
+            port.postMessage({ OpenTab: { url } });
+    }
 });
 
 port.onMessage.addListener((message) => {
-    document.dispatchEvent(
-        new CustomEvent(
-            "voicesurf.native",
-            cloneIntoPolyfill(message, document.defaultView),
-        ),
-    );
+    const { best, action } = message.detail;
+    pendingResults =
+        best && privilegedActions.has(action) ? { best, action } : undefined;
+    try {
+        document.dispatchEvent(
+            new CustomEvent(
+                "voicesurf.native",
+                cloneIntoPolyfill(message, document.defaultView),
+            ),
+        );
+    } finally {
+        pendingResults = undefined;
+    }
 });
 
 const watcher = function () {
@@ -96,13 +156,155 @@ const watcher = function () {
         return elRect.bottom >= 0 && elRect.top - viewHeight < 0;
     }
 
-    document.addEventListener("voicesurf.native", (message) => {
-        for (const elId of message.detail) {
+    /**
+     * Outlines the given elements for a moment, so the user can say
+     * something more specific.
+     */
+    function highlight(els) {
+        for (const el of els) {
+            const outline = el.style.outline;
+            el.style.outline = "3px solid orange";
+            setTimeout(() => (el.style.outline = outline), 2000);
+        }
+    }
+
+    let hintOverlays = [];
+
+    function removeHints() {
+        hintOverlays.forEach((overlay) => overlay.remove());
+        hintOverlays = [];
+    }
+
+    /**
+     * Shows the spoken label of each element next to it, until the next
+     * message from the native host (or Escape).
+     */
+    function showHints(hints) {
+        for (const [elId, label] of hints) {
             const el = et.getElById(elId);
-            if (el && isVisible(el)) {
-                el.click();
+            if (!el) {
+                continue;
+            }
+            const rect = el.getBoundingClientRect();
+            const overlay = document.createElement("div");
+            overlay.textContent = label;
+            Object.assign(overlay.style, {
+                position: "absolute",
+                top: `${rect.top + window.scrollY}px`,
+                left: `${rect.left + window.scrollX}px`,
+                zIndex: "2147483647",
+                padding: "0 2px",
+                background: "yellow",
+                color: "black",
+                font: "bold 12px sans-serif",
+                pointerEvents: "none",
+            });
+            document.body.appendChild(overlay);
+            hintOverlays.push(overlay);
+        }
+    }
+
+    function dispatchToBrowser(detail) {
+        document.dispatchEvent(new CustomEvent("voicesurf.browser", { detail }));
+    }
+
+    /**
+     * Asks the content script to do an action which needs the extension,
+     * with the link it reads from el.
+     */
+    function performInBrowser(action, el) {
+        el.dispatchEvent(
+            new CustomEvent("voicesurf.perform", {
+                bubbles: true,
+                detail: { id: et.elToId.get(el), action },
+            }),
+        );
+    }
+
+    /**
+     * Does what the query asked with the element it found.
+     */
+    function perform(action, el) {
+        const href = el.href;
+        switch (action) {
+            case "OpenInBackgroundTab":
+                if (href) {
+                    performInBrowser(action, el);
+                } else {
+                    el.click();
+                }
+                break;
+            case "Focus":
+                el.focus();
+                break;
+            case "Hover":
+                for (const type of ["mouseover", "mouseenter", "mousemove"]) {
+                    el.dispatchEvent(new MouseEvent(type, { bubbles: true }));
+                }
+                break;
+            case "MiddleClick":
+                // Synthetic middle clicks don't open links, so do that ourselves.
+                el.dispatchEvent(
+                    new MouseEvent("auxclick", { bubbles: true, button: 1 }),
+                );
+                if (href) {
+                    performInBrowser(action, el);
+                }
                 break;
+            case "CopyHref":
+            case "Download":
+                performInBrowser(action, el);
+                break;
+            default:
+                el.click();
+        }
+    }
+
+    /**
+     * Actions which activate or open the link, and so count as clicking it.
+     */
+    const followingActions = new Set([
+        "Click",
+        "OpenInBackgroundTab",
+        "MiddleClick",
+    ]);
+
+    document.addEventListener("keydown", (event) => {
+        if (event.key === "Escape") {
+            removeHints();
+        }
+    });
+
+    document.addEventListener("voicesurf.native", (message) => {
+        removeHints();
+        if (message.detail.hints) {
+            showHints(message.detail.hints);
+            return;
+        }
+        // Results from a search of every tab may be anywhere on the page,
+        // since the user couldn't see it.
+        const { classification, best, action, anywhere } = message.detail;
+        const candidates = best
+            .map((elId) => et.getElById(elId))
+            .filter((el) => el && (anywhere || isVisible(el)));
+        if (classification === "NoMatch" || candidates.length === 0) {
+            return;
+        }
+        if (anywhere) {
+            candidates[0].scrollIntoView({ block: "center" });
+        }
+        // Ambiguous results which aren't on screen can't be what was meant.
+        if (classification === "Confident" || candidates.length === 1) {
+            perform(action, candidates[0]);
+            // Lets the native host learn which link the query meant, when the
+            // link was followed rather than just looked at or copied.
+            if (followingActions.has(action)) {
+                dispatchToBrowser({
+                    Clicked: { id: et.elToId.get(candidates[0]) },
+                });
             }
+        } else {
+            highlight(candidates);
         }
     });
 
@@ -146,24 +348,101 @@ const watcher = function () {
         return false;
     }
 
+    /**
+     * Returns the named fields of el which we index. The native host
+     * weighs each field differently.
+     */
+    function documentFields(el) {
+        // We use textContent here. Using innerText would be better, but
+        // it may require a reflow. This function is called pretty
+        // often, so I think we'd prefer using textContent.
+        const fields = { text: el.textContent };
+        const ariaLabel = el.getAttribute("aria-label");
+        if (ariaLabel) {
+            fields.ariaLabel = ariaLabel;
+        }
+        const title = el.getAttribute("title");
+        if (title) {
+            fields.title = title;
+        }
+        // Icon links usually hold an image with alt text, rather than
+        // having alt text themselves.
+        const alt = el.getAttribute("alt") || el.querySelector("img[alt]")?.alt;
+        if (alt) {
+            fields.alt = alt;
+        }
+        const href = el.getAttribute("href");
+        if (href) {
+            fields.href = href;
+        }
+        return fields;
+    }
+
     /**
      * Watches any given element for text updates.
      */
     const textUpdateObserver = new MutationObserver((mutations) => {
-        let updated = [];
+        // Several mutations often touch the same element.
+        const updatedIds = new Set();
+        const updated = [];
         for (let i = 0; i < mutations.length; i++) {
-            const mutation = mutations[i];
-            const target = mutation.target;
-            const idOrUndefined = et.elToId.get(el);
-            if (idOrUndefined) {
-                updated.push([idOrUndefined, el.textContent]);
+            // The target is usually a text node or element inside the
+            // watched element, so look for the nearest one which is watched.
+            let target = mutations[i].target;
+            let id = et.elToId.get(target);
+            while (id === undefined && target.parentNode) {
+                target = target.parentNode;
+                id = et.elToId.get(target);
+            }
+            if (id !== undefined && !updatedIds.has(id)) {
+                updatedIds.add(id);
+                updated.push([id, documentFields(target)]);
             }
         }
-        document.dispatchEvent(
-            new CustomEvent("voicesurf.browser", {
-                detail: { UpdateIndex: { updated, removed: [] } },
-            }),
-        );
+        if (updated.length > 0) {
+            document.dispatchEvent(
+                new CustomEvent("voicesurf.browser", {
+                    detail: { UpdateIndex: { updated, removed: [] } },
+                }),
+            );
+        }
+    });
+
+    /**
+     * Reports where watched elements are whenever they come into or go
+     * out of view, so the native host can prefer what the user sees.
+     * The browser works this out without forcing a reflow, so it's much
+     * cheaper than measuring every element on every scroll.
+     */
+    const layoutObserver = new IntersectionObserver((entries) => {
+        const layouts = [];
+        for (let i = 0; i < entries.length; i++) {
+            const entry = entries[i];
+            const id = et.elToId.get(entry.target);
+            if (id === undefined) {
+                continue;
+            }
+            const rect = entry.boundingClientRect;
+            layouts.push([
+                id,
+                {
+                    visible: entry.isIntersecting,
+                    top: rect.top + window.scrollY,
+                    left: rect.left + window.scrollX,
+                    width: rect.width,
+                    height: rect.height,
+                    fontSize:
+                        parseFloat(getComputedStyle(entry.target).fontSize) || 0,
+                },
+            ]);
+        }
+        if (layouts.length > 0) {
+            document.dispatchEvent(
+                new CustomEvent("voicesurf.browser", {
+                    detail: { UpdateIndex: { updated: [], removed: [], layouts } },
+                }),
+            );
+        }
     });
 
     function watch(el) {
@@ -173,6 +452,7 @@ const watcher = function () {
             characterData: true,
         });
         const id = et.track(el);
+        layoutObserver.observe(el);
         return id;
     }
 
@@ -180,6 +460,7 @@ const watcher = function () {
         const id = et.untrack(el);
         if (id) {
             textUpdateObserver.disconnect(el);
+            layoutObserver.unobserve(el);
         }
         return id;
     }
@@ -212,14 +493,7 @@ const watcher = function () {
                 const el = addedNodes[j];
                 if (shouldTrack(el)) {
                     const id = watch(el);
-                    // We use textContent here. Using innerText would be
-                    // better, but it may require a reflow. This
-                    // function is called pretty often, so I think we'd
-                    // prefer using textContent.
-                    //
-                    // TODO(kvakil): other accessibility elements and
-                    // what attributes are useful besides textContent?
-                    updatedIndex.updated.push([id, el.textContent]);
+                    updateIndex.updated.push([id, documentFields(el)]);
                 }
             }
         }
@@ -233,13 +507,14 @@ const watcher = function () {
         }
     }).observe(document.body, { childList: true, subtree: true });
 
-    // Compute an initial index.
-    const updateIndex = { updated: [], removed: [] };
+    // Compute an initial index. This has every element on the page, so
+    // the native host can drop anything else it remembers.
+    const updateIndex = { updated: [], removed: [], complete: true };
     selectors
         .flatMap((selector) => Array.from(document.querySelectorAll(selector)))
         .forEach((el) => {
             const id = watch(el);
-            updateIndex.updated.push([id, el.textContent]);
+            updateIndex.updated.push([id, documentFields(el)]);
         });
     document.dispatchEvent(
         new CustomEvent("voicesurf.browser", {
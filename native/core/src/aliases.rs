@@ -0,0 +1,220 @@
+//! Phrases the user has chosen to say for links whose own text is unsayable or
+//! meaningless, like "»" or a username. They are read from
+//! `$XDG_CONFIG_HOME/voicesurf/aliases.json`, which maps domains to lists of aliases:
+//!
+//! ```json
+//! {"news.ycombinator.com": [{"say": "next page", "text": "More"}],
+//!  "*": [{"say": "next", "text": "»"}, {"say": "profile", "href": "/user?id=*"}]}
+//! ```
+use rustc_hash::FxHashMap;
+use std::fs;
+use std::path::PathBuf;
+
+// Aliases under this domain apply on every site.
+const ANY_DOMAIN: &str = "*";
+
+/// A phrase to say for the links matching every condition given. `href` may use `*`
+/// to match any run of characters, while `text` and `ariaLabel` have to match
+/// entirely, ignoring case and spacing.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Alias {
+    /// The phrase, which is indexed as another field of each matching link.
+    pub say: String,
+    /// The text the link must have.
+    #[serde(default)]
+    pub text: Option<String>,
+    /// The pattern the link's address must match.
+    #[serde(default)]
+    pub href: Option<String>,
+    /// The ARIA label the link must have.
+    #[serde(default, rename = "ariaLabel")]
+    pub aria_label: Option<String>,
+}
+
+impl Alias {
+    /// Whether the link with these fields is one this alias is for. An alias without
+    /// any conditions is for no link at all.
+    pub fn matches(&self, fields: &[(&str, &str)]) -> bool {
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(field, _)| *field == name)
+                .map(|(_, content)| *content)
+        };
+        let conditions = [
+            (&self.text, "text"),
+            (&self.href, "href"),
+            (&self.aria_label, "ariaLabel"),
+        ];
+        conditions.iter().any(|(condition, _)| condition.is_some())
+            && conditions
+                .iter()
+                .all(|(condition, name)| match (condition, field(name)) {
+                    (None, _) => true,
+                    (Some(_), None) => false,
+                    (Some(pattern), Some(content)) if *name == "href" => {
+                        wildcard_match(pattern, content)
+                    }
+                    (Some(expected), Some(content)) => normalize(expected) == normalize(content),
+                })
+    }
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Whether `text` matches `pattern`, where `*` in the pattern matches any run of
+/// characters.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    match parts.split_last() {
+        // No wildcards, so the text has to be the pattern.
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(start) => rest = &rest[start + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.ends_with(last)
+        }
+    }
+}
+
+/// The aliases for every site, optionally read from a file.
+#[derive(Debug, Default)]
+pub struct Aliases {
+    path: Option<PathBuf>,
+    aliases_by_domain: FxHashMap<String, Vec<Alias>>,
+}
+
+impl Aliases {
+    /// Aliases which aren't read from a file.
+    pub fn new(aliases_by_domain: FxHashMap<String, Vec<Alias>>) -> Self {
+        Aliases {
+            path: None,
+            aliases_by_domain,
+        }
+    }
+
+    /// Reads the aliases at `path`, which needn't exist yet.
+    pub fn load(path: PathBuf) -> Self {
+        let mut aliases = Aliases {
+            path: Some(path),
+            aliases_by_domain: FxHashMap::default(),
+        };
+        aliases.reload();
+        aliases
+    }
+
+    /// Reads the file again, after it has changed. A malformed file is ignored, so a
+    /// half-saved edit doesn't throw away the aliases in use.
+    pub fn reload(&mut self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        match fs::read_to_string(path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(aliases_by_domain) => self.aliases_by_domain = aliases_by_domain,
+                Err(error) => eprintln!("aliases: ignoring {}: {}", path.display(), error),
+            },
+            Err(_) => self.aliases_by_domain.clear(),
+        }
+    }
+
+    /// The aliases which apply on `domain`, including those for its parent domains and
+    /// for every site.
+    pub fn for_domain(&self, domain: &str) -> Vec<Alias> {
+        let mut aliases: Vec<(&String, &Vec<Alias>)> = self
+            .aliases_by_domain
+            .iter()
+            .filter(|(scope, _)| {
+                scope.as_str() == ANY_DOMAIN
+                    || domain == scope.as_str()
+                    || domain.ends_with(&format!(".{}", scope))
+            })
+            .collect();
+        // Sort the domains, so that the aliases don't depend on the hash order.
+        aliases.sort_by_key(|(scope, _)| *scope);
+        aliases
+            .into_iter()
+            .flat_map(|(_, aliases)| aliases.iter().cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alias(say: &str) -> Alias {
+        Alias {
+            say: say.to_string(),
+            ..Alias::default()
+        }
+    }
+
+    #[test]
+    fn hrefs_match_wildcards() {
+        assert!(wildcard_match("/user?id=*", "/user?id=kvakil"));
+        assert!(wildcard_match(
+            "*/issues/*",
+            "https://github.com/a/b/issues/1"
+        ));
+        assert!(wildcard_match("/about", "/about"));
+        assert!(!wildcard_match("/about", "/about/team"));
+        assert!(!wildcard_match("/user?id=*", "/item?id=1"));
+        assert!(!wildcard_match("*a*a", "a"));
+    }
+
+    #[test]
+    fn aliases_match_every_condition() {
+        let more = Alias {
+            text: Some("More".to_string()),
+            href: Some("?p=*".to_string()),
+            ..alias("next page")
+        };
+        assert!(more.matches(&[("text", "  more "), ("href", "?p=2")]));
+        assert!(!more.matches(&[("text", "More")]));
+        assert!(!more.matches(&[("text", "More"), ("href", "/more")]));
+
+        let close = Alias {
+            aria_label: Some("Close dialog".to_string()),
+            ..alias("close")
+        };
+        assert!(close.matches(&[("text", "×"), ("ariaLabel", "close Dialog")]));
+        assert!(!alias("anything").matches(&[("text", "×")]));
+    }
+
+    #[test]
+    fn aliases_are_scoped_by_domain() {
+        let aliases: FxHashMap<String, Vec<Alias>> = serde_json::from_str(
+            r#"{"ycombinator.com": [{"say": "next page", "text": "More"}],
+                "*": [{"say": "next", "text": "»"}]}"#,
+        )
+        .unwrap();
+        let aliases = Aliases::new(aliases);
+        let says = |domain| -> Vec<String> {
+            aliases
+                .for_domain(domain)
+                .into_iter()
+                .map(|alias| alias.say)
+                .collect()
+        };
+        assert_eq!(says("news.ycombinator.com"), vec!["next", "next page"]);
+        assert_eq!(says("ycombinator.com"), vec!["next", "next page"]);
+        assert_eq!(says("notycombinator.com"), vec!["next"]);
+    }
+}
@@ -0,0 +1,82 @@
+//! The analysis applied to words after tokenizing, both when indexing documents and
+//! when scoring queries, so that both sides agree on what a word is.
+use rust_stemmers::{Algorithm, Stemmer};
+use rustc_hash::FxHashSet;
+
+// Common English words, which say very little about which link was meant.
+const ENGLISH_STOP_WORDS: &[&str] = &[
+    "a", "about", "after", "all", "an", "and", "any", "are", "as", "at", "be", "been", "but", "by",
+    "can", "do", "for", "from", "has", "have", "he", "her", "his", "how", "i", "if", "in", "into",
+    "is", "it", "its", "me", "more", "my", "no", "not", "of", "on", "or", "our", "out", "so",
+    "than", "that", "the", "their", "them", "then", "there", "these", "they", "this", "to", "up",
+    "us", "was", "we", "were", "what", "when", "which", "who", "will", "with", "you", "your",
+];
+
+/// How words are reduced before they are indexed or searched for.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Analysis {
+    /// Which Snowball stemmer to reduce words with, if any, so that "settings" matches
+    /// "setting".
+    pub stemmer: Option<Algorithm>,
+    /// Words which are still indexed (and can be said), but count for little in a
+    /// query.
+    pub stop_words: FxHashSet<String>,
+    /// How much a stop word in a query counts, relative to any other word.
+    pub stop_word_weight: f32,
+}
+
+impl Default for Analysis {
+    fn default() -> Self {
+        Analysis {
+            stemmer: Some(Algorithm::English),
+            stop_words: ENGLISH_STOP_WORDS
+                .iter()
+                .map(|word| word.to_string())
+                .collect(),
+            stop_word_weight: 0.1,
+        }
+    }
+}
+
+impl Analysis {
+    /// Returns the term `word` is indexed and looked up under.
+    pub fn term(&self, word: &str) -> String {
+        match self.stemmer {
+            Some(algorithm) => Stemmer::create(algorithm).stem(word).into_owned(),
+            None => word.to_string(),
+        }
+    }
+
+    /// How much `word` counts when it appears in a query.
+    pub fn query_weight(&self, word: &str) -> f32 {
+        if self.stop_words.contains(word) {
+            self.stop_word_weight
+        } else {
+            1.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stems_words() {
+        let analysis = Analysis::default();
+        assert_eq!(analysis.term("settings"), analysis.term("setting"));
+        assert_eq!(analysis.term("downloads"), analysis.term("downloading"));
+        let analysis = Analysis {
+            stemmer: None,
+            ..Analysis::default()
+        };
+        assert_eq!(analysis.term("settings"), "settings");
+    }
+
+    #[test]
+    fn weighs_stop_words() {
+        let analysis = Analysis::default();
+        assert!(analysis.query_weight("the") < analysis.query_weight("help"));
+    }
+}
@@ -0,0 +1,28 @@
+//! Replacing files so that a reader (or a crash) never sees half of one: the contents
+//! are written to a temporary file, which is then renamed over the real one.
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Writes `contents` to `path` by way of `temporary_path`, which must be on the same
+/// filesystem so that the rename is atomic.
+pub fn write_atomically(temporary_path: &Path, path: &Path, contents: &[u8]) -> io::Result<()> {
+    fs::write(temporary_path, contents)?;
+    fs::rename(temporary_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_replaced_through_the_temporary_path() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("file");
+        let temporary_path = directory.path().join("file.tmp");
+        write_atomically(&temporary_path, &path, b"first").unwrap();
+        write_atomically(&temporary_path, &path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert!(!temporary_path.exists());
+    }
+}
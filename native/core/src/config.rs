@@ -0,0 +1,138 @@
+//! User configuration, read once at startup from `$XDG_CONFIG_HOME/voicesurf/config.json`.
+//! Every field has a default, so the file (and any field in it) is optional.
+use crate::analysis::Analysis;
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+use std::fs;
+
+const CONFIG_FILENAME: &str = "config.json";
+
+/// How documents are ranked against a query.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Ranking {
+    /// Term frequency (normalized by document length) times inverse document frequency.
+    #[default]
+    TfIdf,
+    /// Okapi BM25.
+    Bm25 {
+        /// How quickly repeated words stop adding to the score.
+        k1: f32,
+        /// How strongly long documents are penalized.
+        b: f32,
+    },
+}
+
+/// How much a word in each field of a document counts, relative to the same word in
+/// its text.
+pub type FieldWeights = FxHashMap<String, f32>;
+
+// Fields the browser sends which aren't in the configured weights get this weight.
+const DEFAULT_FIELD_WEIGHT: f32 = 0.5;
+
+fn default_field_weights() -> FieldWeights {
+    [
+        ("text", 1.0),
+        ("ariaLabel", 1.0),
+        ("alt", 0.8),
+        ("title", 0.6),
+        ("href", 0.3),
+        ("alias", 1.0),
+    ]
+    .iter()
+    .map(|&(field, weight)| (field.to_string(), weight))
+    .collect()
+}
+
+/// The weight of `field`, or the default weight if it isn't configured.
+pub fn field_weight(field_weights: &FieldWeights, field: &str) -> f32 {
+    field_weights
+        .get(field)
+        .cloned()
+        .unwrap_or(DEFAULT_FIELD_WEIGHT)
+}
+
+/// When the best result of a query is clear enough to act on.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Confidence {
+    /// Results scoring below this are dropped, and a query with none left is a
+    /// no-match.
+    pub floor: f32,
+    /// The fraction of its score by which the best result must beat the second best,
+    /// or the query is ambiguous.
+    pub margin: f32,
+}
+
+impl Default for Confidence {
+    fn default() -> Self {
+        Confidence {
+            floor: 0.01,
+            margin: 0.2,
+        }
+    }
+}
+
+/// Everything which can be configured, with the defaults for whatever the file leaves
+/// out.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// How documents are ranked against a query.
+    pub ranking: Ranking,
+    /// How much a word in each field counts.
+    ///
+    /// Merged over the default weights, so only the fields which differ need to be
+    /// given.
+    #[serde(deserialize_with = "deserialize_field_weights")]
+    pub field_weights: FieldWeights,
+    /// How words are reduced before they are indexed.
+    pub analysis: Analysis,
+    /// When the best result of a query is clear enough to act on.
+    pub confidence: Confidence,
+    /// How much results from tabs other than the focused one count, when searching
+    /// every tab.
+    pub other_tab_weight: f32,
+    /// How many days it takes for a click to count half as much, when learning which
+    /// links a query means on each site.
+    pub click_half_life_days: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            ranking: Ranking::default(),
+            field_weights: default_field_weights(),
+            analysis: Analysis::default(),
+            confidence: Confidence::default(),
+            other_tab_weight: 0.5,
+            click_half_life_days: 30,
+        }
+    }
+}
+
+fn deserialize_field_weights<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<FieldWeights, D::Error> {
+    let mut field_weights = default_field_weights();
+    field_weights.extend(FieldWeights::deserialize(deserializer)?);
+    Ok(field_weights)
+}
+
+/// Loads the configuration file, falling back to the defaults if it is missing or
+/// malformed.
+pub fn load(xdg_dirs: &xdg::BaseDirectories) -> Config {
+    let path = match xdg_dirs.find_config_file(CONFIG_FILENAME) {
+        Some(path) => path,
+        None => return Config::default(),
+    };
+    match fs::read_to_string(&path)
+        .map_err(|error| error.to_string())
+        .and_then(|contents| serde_json::from_str(&contents).map_err(|error| error.to_string()))
+    {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("config: ignoring {}: {}", path.display(), error);
+            Config::default()
+        }
+    }
+}
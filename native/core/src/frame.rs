@@ -0,0 +1,67 @@
+//! Length-prefixed frames, which is how messages are sent over the Talon socket. Each
+//! frame is its length as a big-endian u32, followed by that many bytes of JSON.
+use std::io;
+use std::io::{Read, Write};
+
+/// Frames longer than this are refused, rather than trusting a bad length to allocate.
+pub const MAX_FRAME_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Writes `frame` with its length.
+pub fn write_frame(writer: &mut impl Write, frame: &[u8]) -> io::Result<()> {
+    if frame.len() > MAX_FRAME_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "frame is too long",
+        ));
+    }
+    writer.write_all(&(frame.len() as u32).to_be_bytes())?;
+    writer.write_all(frame)?;
+    writer.flush()
+}
+
+/// Reads the next frame, or `None` if the other end closed the connection between
+/// frames. Closing it in the middle of one is an error.
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length[..1]) {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error),
+    }
+    reader.read_exact(&mut length[1..])?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "frame is too long",
+        ));
+    }
+    let mut frame = vec![0; length];
+    reader.read_exact(&mut frame)?;
+    Ok(Some(frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_round_trip() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, b"{}").unwrap();
+        write_frame(&mut buffer, b"").unwrap();
+        assert_eq!(&buffer[..6], b"\0\0\0\x02{}");
+
+        let mut reader = buffer.as_slice();
+        assert_eq!(read_frame(&mut reader).unwrap(), Some(b"{}".to_vec()));
+        assert_eq!(read_frame(&mut reader).unwrap(), Some(Vec::new()));
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn truncated_and_oversized_frames_are_errors() {
+        assert!(read_frame(&mut &b"\0\0"[..]).is_err());
+        assert!(read_frame(&mut &b"\0\0\0\x05{}"[..]).is_err());
+        assert!(read_frame(&mut &b"\xff\xff\xff\xff"[..]).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! A BK-tree over the words in an index, for finding words within a small edit
+//! distance of a query word. Dictation engines aren't limited to the words we send to
+//! Talon, so they regularly produce near misses ("setings", "acount").
+use rustc_hash::FxHashMap;
+use std::collections::hash_map::Entry;
+
+/// Levenshtein distance between `a` and `b`, counted in characters.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous_row: Vec<usize> = (0..=b.len()).collect();
+    let mut current_row = vec![0; b.len() + 1];
+    for (i, a_ch) in a.chars().enumerate() {
+        current_row[0] = i + 1;
+        for (j, &b_ch) in b.iter().enumerate() {
+            let substitution = previous_row[j] + if a_ch == b_ch { 0 } else { 1 };
+            current_row[j + 1] = substitution
+                .min(previous_row[j + 1] + 1)
+                .min(current_row[j] + 1);
+        }
+        std::mem::swap(&mut previous_row, &mut current_row);
+    }
+    previous_row[b.len()]
+}
+
+#[derive(Debug)]
+struct Node {
+    word: String,
+    children: FxHashMap<usize, Node>,
+}
+
+/// Words can't be removed from a BK-tree, so callers should rebuild the tree once it
+/// holds too many words they no longer need.
+#[derive(Debug, Default)]
+pub struct BkTree {
+    root: Option<Node>,
+    len: usize,
+}
+
+impl BkTree {
+    /// Adds `word` to the tree, returning whether it was new.
+    pub fn insert(&mut self, word: &str) -> bool {
+        let mut node = match &mut self.root {
+            Some(node) => node,
+            None => {
+                self.root = Some(Node {
+                    word: word.to_string(),
+                    children: FxHashMap::default(),
+                });
+                self.len += 1;
+                return true;
+            }
+        };
+        loop {
+            let distance = edit_distance(&node.word, word);
+            if distance == 0 {
+                return false;
+            }
+            node = match node.children.entry(distance) {
+                // Descend, since the word may already be further down.
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    entry.insert(Node {
+                        word: word.to_string(),
+                        children: FxHashMap::default(),
+                    });
+                    self.len += 1;
+                    return true;
+                }
+            };
+        }
+    }
+
+    /// The number of words in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns every word within `max_distance` edits of `word`, with its distance.
+    pub fn find(&self, word: &str, max_distance: usize) -> Vec<(&str, usize)> {
+        let mut found = Vec::new();
+        let mut pending: Vec<&Node> = self.root.iter().collect();
+        while let Some(node) = pending.pop() {
+            let distance = edit_distance(&node.word, word);
+            if distance <= max_distance {
+                found.push((node.word.as_str(), distance));
+            }
+            // By the triangle inequality, only children whose distance to this node is
+            // within max_distance of ours can be close enough to the word.
+            let low = distance.saturating_sub(max_distance);
+            let high = distance + max_distance;
+            pending.extend(
+                node.children
+                    .iter()
+                    .filter(|(&child_distance, _)| low <= child_distance && child_distance <= high)
+                    .map(|(_, child)| child),
+            );
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("settings", "setings"), 1);
+        assert_eq!(edit_distance("café", "cafe"), 1);
+    }
+
+    #[test]
+    fn finds_close_words() {
+        let mut tree = BkTree::default();
+        [
+            "settings", "setting", "account", "accounts", "help", "sitting",
+        ]
+        .iter()
+        .for_each(|word| assert!(tree.insert(word)));
+        assert!(!tree.insert("settings"));
+        assert_eq!(tree.len(), 6);
+
+        assert_eq!(tree.find("setings", 1), vec![("settings", 1)]);
+        let mut found = tree.find("setings", 2);
+        found.sort();
+        assert_eq!(found, vec![("setting", 2), ("settings", 1)]);
+        let mut found = tree.find("acount", 2);
+        found.sort();
+        assert_eq!(found, vec![("account", 1), ("accounts", 2)]);
+        assert_eq!(tree.find("zebra", 2), vec![]);
+    }
+
+    #[test]
+    fn words_below_the_root_are_only_counted_once() {
+        let mut tree = BkTree::default();
+        assert!(tree.insert("settings"));
+        assert!(tree.insert("setting"));
+        assert!(tree.insert("sitting"));
+        assert!(!tree.insert("setting"));
+        assert!(!tree.insert("sitting"));
+        assert_eq!(tree.len(), 3);
+    }
+}
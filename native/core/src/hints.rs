@@ -0,0 +1,90 @@
+//! Spoken labels for the links on screen, for when their text can't tell them apart
+//! (say, a page full of "Reply" links). Labels are words from Talon's phonetic
+//! alphabet, and all the labels shown at once are the same number of words long, so
+//! none is the start of another.
+use crate::index::DocumentId;
+use rustc_hash::FxHashMap;
+
+/// Talon's words for the letters "a" to "z", in order.
+pub const PHONETIC_ALPHABET: [&str; 26] = [
+    "air", "bat", "cap", "drum", "each", "fine", "gust", "harp", "sit", "jury", "crunch", "look",
+    "made", "near", "odd", "pit", "quench", "red", "sun", "trap", "urge", "vest", "whale", "plex",
+    "yank", "zip",
+];
+
+/// `count` distinct labels, as short as they can be.
+pub fn labels(count: usize) -> Vec<String> {
+    let mut length = 1;
+    while PHONETIC_ALPHABET.len().pow(length) < count {
+        length += 1;
+    }
+    (0..count)
+        .map(|mut number| {
+            let mut words = vec![""; length as usize];
+            for word in words.iter_mut().rev() {
+                *word = PHONETIC_ALPHABET[number % PHONETIC_ALPHABET.len()];
+                number /= PHONETIC_ALPHABET.len();
+            }
+            words.join(" ")
+        })
+        .collect()
+}
+
+/// The labels shown on a page, and the documents they are for.
+#[derive(Debug, Default)]
+pub struct Hints {
+    documents_by_label: FxHashMap<String, DocumentId>,
+}
+
+impl Hints {
+    /// Labels `document_ids`, in order.
+    pub fn new(document_ids: &[DocumentId]) -> Self {
+        Hints {
+            documents_by_label: labels(document_ids.len())
+                .into_iter()
+                .zip(document_ids.iter().cloned())
+                .collect(),
+        }
+    }
+
+    /// Each label with the document it is for, in no particular order.
+    pub fn labels(&self) -> impl Iterator<Item = (&str, DocumentId)> + '_ {
+        self.documents_by_label
+            .iter()
+            .map(|(label, &document_id)| (label.as_str(), document_id))
+    }
+
+    /// The document labelled `label`, however it was capitalized and spaced.
+    pub fn resolve(&self, label: &str) -> Option<DocumentId> {
+        let label = label
+            .split_whitespace()
+            .map(|word| word.to_lowercase())
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.documents_by_label.get(&label).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_are_as_short_as_they_can_be() {
+        assert_eq!(labels(3), vec!["air", "bat", "cap"]);
+        assert_eq!(labels(26).len(), 26);
+        let labels = labels(27);
+        assert_eq!(labels[0], "air air");
+        assert_eq!(labels[26], "bat air");
+        assert!(labels.iter().all(|label| label.split(' ').count() == 2));
+    }
+
+    #[test]
+    fn labels_resolve_to_documents() {
+        let hints = Hints::new(&[7, 3]);
+        assert_eq!(hints.resolve("air"), Some(7));
+        assert_eq!(hints.resolve(" Bat "), Some(3));
+        assert_eq!(hints.resolve("cap"), None);
+        assert_eq!(hints.labels().count(), 2);
+    }
+}
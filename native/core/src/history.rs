@@ -0,0 +1,228 @@
+//! Which links were clicked for which queries on each site, so that a query which
+//! usually means one link ranks it first. Clicks fade with a half-life, so old habits
+//! don't outweigh new ones forever.
+use crate::atomic::write_atomically;
+use crate::tokenizer::tokenize;
+use rustc_hash::FxHashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+// How much a link clicked for a query is boosted by, at most. Each click counts for
+// less than the last, so one click gives half of this.
+const CLICK_BOOST: f32 = 0.5;
+
+// Clicks which have faded below this are forgotten.
+const MIN_CLICK_WEIGHT: f32 = 0.01;
+
+/// What identifies a link across page loads, since document ids don't survive them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LinkKey {
+    /// The link's text, normalized.
+    pub text: String,
+    /// The link's address.
+    pub href: String,
+}
+
+impl LinkKey {
+    /// The key of the link with `text` and `href`.
+    pub fn new(text: &str, href: &str) -> Self {
+        LinkKey {
+            text: normalize(text),
+            href: href.to_string(),
+        }
+    }
+}
+
+/// Queries and link texts which differ only in case, accents or spacing are the same.
+fn normalize(text: &str) -> String {
+    tokenize(text).join(" ")
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Click {
+    query: String,
+    link: LinkKey,
+    // Decayed to `time`, in seconds since the Unix epoch.
+    weight: f32,
+    time: u64,
+}
+
+fn decayed_weight(click: &Click, half_life: u64, now: u64) -> f32 {
+    if half_life == 0 {
+        return click.weight;
+    }
+    let elapsed = now.saturating_sub(click.time) as f32;
+    click.weight * 0.5f32.powf(elapsed / half_life as f32)
+}
+
+/// The clicks on every site, optionally kept in a file.
+#[derive(Debug)]
+pub struct ClickHistory {
+    path: Option<PathBuf>,
+    half_life: u64,
+    clicks_by_domain: FxHashMap<String, Vec<Click>>,
+}
+
+impl ClickHistory {
+    /// An empty history which is never saved, where clicks lose half their weight every
+    /// `half_life` seconds.
+    pub fn new(half_life: u64) -> Self {
+        ClickHistory {
+            path: None,
+            half_life,
+            clicks_by_domain: FxHashMap::default(),
+        }
+    }
+
+    /// Loads the history saved at `path`, or starts a new one if there is none there
+    /// yet. Either way, it will be saved back there.
+    pub fn load(path: PathBuf, half_life: u64) -> Self {
+        let clicks_by_domain = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|error| {
+                eprintln!("history: ignoring {}: {}", path.display(), error);
+                FxHashMap::default()
+            }),
+            Err(_) => FxHashMap::default(),
+        };
+        ClickHistory {
+            path: Some(path),
+            half_life,
+            clicks_by_domain,
+        }
+    }
+
+    /// Records that `link` was clicked for `query` on `domain` at `now`.
+    pub fn record(&mut self, domain: &str, query: &str, link: LinkKey, now: u64) {
+        let query = normalize(query);
+        let half_life = self.half_life;
+        let clicks = self.clicks_by_domain.entry(domain.to_string()).or_default();
+        clicks.iter_mut().for_each(|click| {
+            click.weight = decayed_weight(click, half_life, now);
+            click.time = now;
+        });
+        clicks.retain(|click| click.weight >= MIN_CLICK_WEIGHT);
+        match clicks
+            .iter_mut()
+            .find(|click| click.query == query && click.link == link)
+        {
+            Some(click) => click.weight += 1.0,
+            None => clicks.push(Click {
+                query,
+                link,
+                weight: 1.0,
+                time: now,
+            }),
+        }
+    }
+
+    /// How much to add to the score of each link on `domain` for `query` at `now`.
+    pub fn boosts(&self, domain: &str, query: &str, now: u64) -> FxHashMap<LinkKey, f32> {
+        let query = normalize(query);
+        self.clicks_by_domain
+            .get(domain)
+            .into_iter()
+            .flatten()
+            .filter(|click| click.query == query)
+            .map(|click| {
+                let weight = decayed_weight(click, self.half_life, now);
+                (click.link.clone(), CLICK_BOOST * weight / (1.0 + weight))
+            })
+            .collect()
+    }
+
+    /// Forgets the clicks on `domain`, or on every site.
+    pub fn reset(&mut self, domain: Option<&str>) {
+        match domain {
+            Some(domain) => {
+                self.clicks_by_domain.remove(domain);
+            }
+            None => self.clicks_by_domain.clear(),
+        }
+    }
+
+    /// Saves the history to its file, if it has one.
+    pub fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        write_atomically(
+            &path.with_extension("tmp"),
+            path,
+            serde_json::to_string(&self.clicks_by_domain)?.as_bytes(),
+        )
+    }
+}
+
+/// The host of `url`, which is what clicks are grouped by.
+pub fn domain(url: &str) -> &str {
+    let rest = url.split("://").nth(1).unwrap_or(url);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+    let host = authority.rsplit('@').next().unwrap_or(authority);
+    host.split(':').next().unwrap_or(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    #[test]
+    fn finds_domains() {
+        assert_eq!(
+            domain("https://mail.example.com/u/0/#inbox"),
+            "mail.example.com"
+        );
+        assert_eq!(domain("http://user@localhost:8080?q=1"), "localhost");
+        assert_eq!(domain("about:blank"), "about");
+    }
+
+    #[test]
+    fn clicks_boost_links_for_the_same_query() {
+        let mut history = ClickHistory::new(30 * DAY);
+        let inbox = LinkKey::new("Inbox", "/inbox");
+        history.record("mail.example.com", "in", inbox.clone(), 0);
+
+        let boosts = history.boosts("mail.example.com", "In", 0);
+        assert_eq!(boosts[&inbox], 0.25);
+        assert!(history.boosts("mail.example.com", "index", 0).is_empty());
+        assert!(history.boosts("example.com", "in", 0).is_empty());
+
+        history.record("mail.example.com", "in", inbox.clone(), 0);
+        assert!(history.boosts("mail.example.com", "in", 0)[&inbox] > 0.25);
+    }
+
+    #[test]
+    fn clicks_decay() {
+        let mut history = ClickHistory::new(30 * DAY);
+        let inbox = LinkKey::new("Inbox", "/inbox");
+        let spam = LinkKey::new("Spam", "/spam");
+        history.record("mail.example.com", "in", inbox.clone(), 0);
+        history.record("mail.example.com", "in", spam.clone(), 60 * DAY);
+
+        let boosts = history.boosts("mail.example.com", "in", 60 * DAY);
+        assert!(boosts[&spam] > boosts[&inbox]);
+        assert!((boosts[&inbox] - CLICK_BOOST * 0.25 / 1.25).abs() < 1e-6);
+
+        // Long forgotten clicks are dropped when the site is next clicked on.
+        history.record("mail.example.com", "in", spam, 2000 * DAY);
+        assert!(!history
+            .boosts("mail.example.com", "in", 2000 * DAY)
+            .contains_key(&inbox));
+    }
+
+    #[test]
+    fn clicks_can_be_reset() {
+        let mut history = ClickHistory::new(30 * DAY);
+        history.record("mail.example.com", "in", LinkKey::new("Inbox", "/inbox"), 0);
+        history.record("example.com", "home", LinkKey::new("Home", "/"), 0);
+
+        history.reset(Some("mail.example.com"));
+        assert!(history.boosts("mail.example.com", "in", 0).is_empty());
+        assert_eq!(history.boosts("example.com", "home", 0).len(), 1);
+        history.reset(None);
+        assert!(history.boosts("example.com", "home", 0).is_empty());
+    }
+}
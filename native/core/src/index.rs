@@ -0,0 +1,1609 @@
+//! The search index over the clickable elements of a page. Each element is a document,
+//! which the browser sends as text or as named fields, and queries are whatever the
+//! user said.
+use crate::aliases::Alias;
+use crate::analysis::Analysis;
+use crate::config::{field_weight, Config, FieldWeights, Ranking};
+use crate::fuzzy::BkTree;
+use crate::history::LinkKey;
+use crate::ngram::ngrams;
+use crate::phonetic::phonetic_key;
+use crate::spoken::{spoken_form_map, spoken_forms, SpokenForms};
+use crate::tokenizer::tokenize;
+use bincode::Options;
+use itertools::Itertools;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::collections::hash_map::Entry;
+
+// How much a word which only sounds like the query word counts, relative to an exact
+// match.
+const PHONETIC_MATCH_WEIGHT: f32 = 0.5;
+
+// How much a word which is one edit away from the query word counts, relative to an
+// exact match. Each further edit multiplies this again.
+const FUZZY_MATCH_WEIGHT: f32 = 0.4;
+
+// Words this short have too many neighbors to be matched fuzzily, and longer words are
+// allowed one more edit.
+const MIN_FUZZY_WORD_LENGTH: usize = 4;
+const MIN_TWO_EDIT_WORD_LENGTH: usize = 7;
+
+// How much an index word which starts with (or merely contains) the query word counts,
+// relative to an exact match. This is further scaled by how much of the index word the
+// query word covers.
+const PREFIX_MATCH_WEIGHT: f32 = 0.6;
+const SUBSTRING_MATCH_WEIGHT: f32 = 0.3;
+
+// For queries with several words, a document's score is multiplied by
+// 1 + COVERAGE_BONUS * (fraction of query words it contains)
+//   + PROXIMITY_BONUS * (how close together and in order they are).
+const COVERAGE_BONUS: f32 = 1.0;
+const PROXIMITY_BONUS: f32 = 1.0;
+
+// A document's score is multiplied by
+// (1 + VISIBLE_BONUS if it's on screen) * (1 + SIZE_BONUS * size + POSITION_BONUS * height),
+// where size is between -1 and 1, from how its font and box compare to typical ones, and
+// height falls from 1 at the top of the page towards 0. Documents without a layout are
+// left alone.
+const VISIBLE_BONUS: f32 = 1.0;
+const SIZE_BONUS: f32 = 0.2;
+const POSITION_BONUS: f32 = 0.2;
+const TYPICAL_FONT_SIZE: f32 = 16.0;
+const TYPICAL_BOX_SIZE: f32 = 50.0;
+// How far down the page (in CSS pixels) the position bonus is halved.
+const HALF_POSITION_BONUS_DEPTH: f32 = 1000.0;
+
+// Documents sent as plain text have just this field.
+const TEXT_FIELD: &str = "text";
+const HREF_FIELD: &str = "href";
+// Documents are also indexed under the phrases of the aliases which match them.
+const ALIAS_FIELD: &str = "alias";
+
+// How far apart (in words) consecutive fields of a document are placed, so phrases
+// never match across fields.
+const FIELD_POSITION_GAP: usize = 100;
+
+/// The browser's id for an element, which is unique within its tab.
+pub type DocumentId = usize;
+/// The name of a field of an element, like "text" or "href".
+pub type FieldName = String;
+/// An element, as the browser sends it.
+pub type Document = (DocumentId, DocumentContent);
+/// A word, as it is indexed.
+pub type Word = String;
+type PhoneticKey = String;
+type Ngram = String;
+/// How well a document matches a query.
+pub type Score = f32;
+/// The scores of the documents which match a query.
+pub type ScoreResult = FxHashMap<DocumentId, Score>;
+type Position = usize;
+type PositionsByDocument = FxHashMap<DocumentId, Vec<Position>>;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WordIndex {
+    frequency_by_document: FxHashMap<DocumentId, f32>,
+    positions_by_document: PositionsByDocument,
+}
+
+/// An index of the documents on one page.
+// Snapshots only hold what can't be rebuilt from the document bags, plus the settings
+// which shaped the stored frequencies and terms.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TfidfIndex {
+    number_of_documents: usize,
+    document_bags: FxHashMap<DocumentId, FxHashSet<String>>,
+    // The words in each document's bag which are only in its href, like "https" or the
+    // ids in a query string. They can be searched for, but aren't worth saying.
+    document_unsaid_words: FxHashMap<DocumentId, FxHashSet<Word>>,
+    word_indices: FxHashMap<Word, WordIndex>,
+    #[serde(skip)]
+    phonetic_indices: FxHashMap<PhoneticKey, FxHashSet<Word>>,
+    // This also holds words which are no longer in any document, until it's compacted.
+    #[serde(skip)]
+    fuzzy_index: BkTree,
+    // How many documents each word is in.
+    #[serde(skip)]
+    live_words: FxHashMap<Word, usize>,
+    // How many documents each word is said in, which is what Talon is told about.
+    #[serde(skip)]
+    vocabulary: FxHashMap<Word, usize>,
+    #[serde(skip)]
+    ngram_indices: FxHashMap<Ngram, FxHashSet<Word>>,
+    document_lengths: FxHashMap<DocumentId, usize>,
+    total_document_length: usize,
+    // For finding the documents which were clicked on before.
+    document_links: FxHashMap<DocumentId, LinkKey>,
+    // The fields as the browser sent them, so documents can be indexed again when the
+    // aliases change, along with the aliases they were indexed with.
+    document_fields: FxHashMap<DocumentId, Vec<(FieldName, String)>>,
+    aliases: Vec<Alias>,
+    // Layouts change whenever the page is scrolled, so they aren't worth keeping.
+    #[serde(skip)]
+    document_layouts: FxHashMap<DocumentId, Layout>,
+    #[serde(skip)]
+    ranking: Ranking,
+    field_weights: FieldWeights,
+    analysis: Analysis,
+    // Spoken words which came into or went out of the index since Talon was last told.
+    #[serde(skip)]
+    vocabulary_changes: VocabularyChanges,
+}
+
+/// Spoken words which came into or went out of an index.
+#[derive(Debug, Default, PartialEq)]
+pub struct VocabularyChanges {
+    /// Words which are new to the index.
+    pub added: FxHashSet<Word>,
+    /// Words which are no longer in the index.
+    pub removed: FxHashSet<Word>,
+}
+
+impl VocabularyChanges {
+    /// Whether there is nothing to tell Talon.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Where an element is on the page and how it looks, which the browser sends separately
+/// from its fields, since it changes far more often.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Layout {
+    /// Whether any of it is in the viewport.
+    pub visible: bool,
+    /// Its bounding box, in CSS pixels from the top left of the page.
+    pub top: f32,
+    /// See `top`.
+    pub left: f32,
+    /// See `top`.
+    pub width: f32,
+    /// See `top`.
+    pub height: f32,
+    /// The size of its text, in CSS pixels.
+    #[serde(rename = "fontSize")]
+    pub font_size: f32,
+}
+
+impl Layout {
+    /// How much to multiply the score of a document with this layout by.
+    fn prior(&self) -> f32 {
+        // How many times bigger than typical, up to twice (or half) as big.
+        let relative_size = |size: f32, typical: f32| (size / typical).clamp(0.5, 2.0).log2();
+        let size = 0.5 * relative_size(self.font_size, TYPICAL_FONT_SIZE)
+            + 0.5 * relative_size((self.width * self.height).sqrt(), TYPICAL_BOX_SIZE);
+        let height = 1.0 / (1.0 + self.top.max(0.0) / HALF_POSITION_BONUS_DEPTH);
+        let visible = if self.visible { VISIBLE_BONUS } else { 0.0 };
+        (1.0 + visible) * (1.0 + SIZE_BONUS * size + POSITION_BONUS * height)
+    }
+}
+
+/// The browser either sends the text of an element, or its named fields ("text",
+/// "ariaLabel", "alt", "title", "href", ...).
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DocumentContent {
+    /// The element's text, which is indexed as its "text" field.
+    Text(String),
+    /// The element's fields, by name.
+    Fields(FxHashMap<FieldName, String>),
+}
+
+impl TfidfIndex {
+    /// An empty index, ranking and analyzing words as configured.
+    pub fn new(config: &Config) -> Self {
+        TfidfIndex {
+            number_of_documents: 0,
+            word_indices: FxHashMap::<Word, WordIndex>::default(),
+            document_bags: FxHashMap::<DocumentId, FxHashSet<String>>::default(),
+            document_unsaid_words: FxHashMap::<DocumentId, FxHashSet<Word>>::default(),
+            phonetic_indices: FxHashMap::<PhoneticKey, FxHashSet<Word>>::default(),
+            fuzzy_index: BkTree::default(),
+            live_words: FxHashMap::<Word, usize>::default(),
+            vocabulary: FxHashMap::<Word, usize>::default(),
+            ngram_indices: FxHashMap::<Ngram, FxHashSet<Word>>::default(),
+            document_lengths: FxHashMap::<DocumentId, usize>::default(),
+            total_document_length: 0,
+            document_links: FxHashMap::<DocumentId, LinkKey>::default(),
+            document_fields: FxHashMap::<DocumentId, Vec<(FieldName, String)>>::default(),
+            aliases: vec![],
+            document_layouts: FxHashMap::<DocumentId, Layout>::default(),
+            ranking: config.ranking,
+            field_weights: config.field_weights.clone(),
+            analysis: config.analysis.clone(),
+            vocabulary_changes: VocabularyChanges::default(),
+        }
+    }
+
+    /// Restores an index from `snapshot`, returning `None` if it is malformed or was
+    /// taken with different field weights or analysis.
+    pub fn from_snapshot(config: &Config, snapshot: &[u8]) -> Option<Self> {
+        let mut tfidf_index: TfidfIndex = snapshot_options().deserialize(snapshot).ok()?;
+        if tfidf_index.field_weights != config.field_weights
+            || tfidf_index.analysis != config.analysis
+            || !tfidf_index.is_consistent()
+        {
+            return None;
+        }
+        tfidf_index.ranking = config.ranking;
+        let words: Vec<(Word, bool)> = tfidf_index
+            .document_bags
+            .iter()
+            .flat_map(|(document_id, bag)| {
+                let unsaid = tfidf_index.document_unsaid_words.get(document_id);
+                bag.iter().map(move |word| {
+                    let said = unsaid.is_none_or(|unsaid| !unsaid.contains(word));
+                    (word.clone(), said)
+                })
+            })
+            .collect();
+        words.iter().for_each(|(word, said)| {
+            tfidf_index.add_live_word(word);
+            if *said {
+                tfidf_index.add_vocabulary_word(word);
+            }
+        });
+        Some(tfidf_index)
+    }
+
+    /// Whether every document the word indices refer to is in the index, which scoring
+    /// relies on. A snapshot from disk might not be.
+    fn is_consistent(&self) -> bool {
+        self.number_of_documents == self.document_bags.len()
+            && self.word_indices.values().all(|word_index| {
+                word_index.frequency_by_document.keys().all(|document_id| {
+                    self.document_bags.contains_key(document_id)
+                        && self.document_lengths.contains_key(document_id)
+                })
+            })
+    }
+
+    /// A compact serialization of the index, which `from_snapshot` restores. Fails if
+    /// the index is too big to be restored.
+    pub fn snapshot(&self) -> bincode::Result<Vec<u8>> {
+        snapshot_options().serialize(self)
+    }
+
+    /// The ids of the documents in the index.
+    pub fn document_ids(&self) -> impl Iterator<Item = DocumentId> + '_ {
+        self.document_bags.keys().cloned()
+    }
+
+    fn add_word_scores(&self, word_index: &WordIndex, weight: f32, scores: &mut ScoreResult) {
+        let number_of_documents = self.number_of_documents as f32;
+        let document_frequency = word_index.frequency_by_document.len() as f32;
+        match self.ranking {
+            Ranking::TfIdf => {
+                // Smoothed, so that a word in every document still counts for something:
+                // otherwise a page with one link, or only "Reply" links, matches nothing.
+                // This was ln((1 + N) / (1 + df)), and the change affects every query, not
+                // just those: each word's idf is higher (ln 3 rather than ln 3/2 for a
+                // word in one of two documents), and common words lose less to rare ones,
+                // so multi-word queries weigh their words more evenly. The order of
+                // documents matching a single word is unchanged.
+                let idf = (1.0 + number_of_documents / document_frequency).ln();
+                word_index
+                    .frequency_by_document
+                    .iter()
+                    .for_each(|(&document_id, &tf)| {
+                        let score = scores.entry(document_id).or_insert(0.0);
+                        *score += weight * tf * idf;
+                    });
+            }
+            Ranking::Bm25 { k1, b } => {
+                let idf = (1.0
+                    + (number_of_documents - document_frequency + 0.5)
+                        / (document_frequency + 0.5))
+                    .ln();
+                let average_document_length =
+                    (self.total_document_length as f32 / number_of_documents).max(1.0);
+                word_index
+                    .frequency_by_document
+                    .iter()
+                    .for_each(|(&document_id, &tf)| {
+                        let document_length = self.document_lengths[&document_id] as f32;
+                        // We store the term frequency normalized by the document length.
+                        let count = tf * document_length;
+                        let length_normalization =
+                            k1 * (1.0 - b + b * document_length / average_document_length);
+                        let score = scores.entry(document_id).or_insert(0.0);
+                        *score +=
+                            weight * idf * count * (k1 + 1.0) / (count + length_normalization);
+                    });
+            }
+        }
+    }
+
+    /// Scores every document containing the query words, or words similar to them.
+    /// Documents which match nothing are left out.
+    pub fn score(&self, query: &str) -> ScoreResult {
+        let mut scores = FxHashMap::<DocumentId, Score>::default();
+        // Where each query word (or a word similar to it) appears in each document, in
+        // query order.
+        let mut matches = Vec::<PositionsByDocument>::new();
+        let analysis = &self.analysis;
+        // TODO(kvakil): do we want .unique()?
+        tokenize(query)
+            .iter()
+            .map(|word| (word, analysis.term(word), analysis.query_weight(word)))
+            .unique_by(|(_, term, _)| term.to_string())
+            .for_each(|(word, term, query_weight)| {
+                let mut positions = PositionsByDocument::default();
+                let mut add_matches = |word_index: &WordIndex, weight: f32| {
+                    self.add_word_scores(word_index, query_weight * weight, &mut scores);
+                    word_index.positions_by_document.iter().for_each(
+                        |(&document_id, word_positions)| {
+                            positions
+                                .entry(document_id)
+                                .or_default()
+                                .extend(word_positions)
+                        },
+                    );
+                };
+                match self.word_indices.get(&term) {
+                    Some(word_index) if !word_index.frequency_by_document.is_empty() => {
+                        add_matches(word_index, 1.0)
+                    }
+                    _ => self
+                        .similar_terms(word)
+                        .into_iter()
+                        .filter_map(|(similar_term, weight)| {
+                            self.word_indices
+                                .get(&similar_term)
+                                .map(|word_index| (word_index, weight))
+                        })
+                        .for_each(|(word_index, weight)| add_matches(word_index, weight)),
+                }
+                matches.push(positions);
+            });
+        if matches.len() > 1 {
+            scores.iter_mut().for_each(|(document_id, score)| {
+                *score *= phrase_bonus(&matches, *document_id);
+            });
+        }
+        scores.iter_mut().for_each(|(document_id, score)| {
+            if let Some(layout) = self.document_layouts.get(document_id) {
+                *score *= layout.prior();
+            }
+        });
+        scores
+    }
+
+    /// Like `score`, but adds `boosts` (from `ClickHistory::boosts`) to the documents
+    /// with those links, even if they didn't otherwise match the query.
+    pub fn score_with_boosts(&self, query: &str, boosts: &FxHashMap<LinkKey, f32>) -> ScoreResult {
+        let mut scores = self.score(query);
+        if !boosts.is_empty() {
+            self.document_links.iter().for_each(|(&document_id, link)| {
+                if let Some(boost) = boosts.get(link) {
+                    *scores.entry(document_id).or_insert(0.0) += boost;
+                }
+            });
+        }
+        scores
+    }
+
+    /// Scores each of a recognizer's alternative hearings of a query, given with how
+    /// likely each is, and combines them with `combine_scores`.
+    pub fn score_alternatives(&self, alternatives: &[(String, f32)]) -> ScoreResult {
+        self.score_alternatives_with_boosts(alternatives, |_| FxHashMap::default())
+            .0
+    }
+
+    /// Like `score_alternatives`, but scores each hearing with the boosts `boosts` gives
+    /// for it, as `score_with_boosts` does. Also returns the likeliest hearing which
+    /// matched anything, which is the one a click on the results is credited to.
+    pub fn score_alternatives_with_boosts<'a>(
+        &self,
+        alternatives: &'a [(String, f32)],
+        boosts: impl Fn(&str) -> FxHashMap<LinkKey, f32>,
+    ) -> (ScoreResult, Option<&'a str>) {
+        let scores: Vec<(ScoreResult, f32)> = alternatives
+            .iter()
+            .map(|(query, weight)| (self.score_with_boosts(query, &boosts(query)), *weight))
+            .collect();
+        let likeliest = alternatives
+            .iter()
+            .zip(&scores)
+            .filter(|(_, (scores, _))| !scores.is_empty())
+            .max_by(|((_, weight0), _), ((_, weight1), _)| {
+                weight0
+                    .partial_cmp(weight1)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .map(|((query, _), _)| query.as_str());
+        (combine_scores(scores), likeliest)
+    }
+
+    /// The link a document was indexed with, which identifies it across page loads.
+    pub fn link(&self, document_id: DocumentId) -> Option<&LinkKey> {
+        self.document_links.get(&document_id)
+    }
+
+    /// Returns the terms of the words in the index which are similar to `word`, with how
+    /// much each should count.
+    fn similar_terms(&self, word: &str) -> FxHashMap<Word, f32> {
+        let mut weights = FxHashMap::<Word, f32>::default();
+        self.similar_words(word)
+            .into_iter()
+            .for_each(|(similar_word, weight)| {
+                let best_weight = weights
+                    .entry(self.analysis.term(similar_word))
+                    .or_insert(0.0);
+                *best_weight = best_weight.max(weight);
+            });
+        weights
+    }
+
+    /// Nothing is spelled like `word`, but the recognizer may have picked the wrong
+    /// homophone, the user may have made a typo, or only said part of a word. Returns the
+    /// words in the index which sound the same, are spelled similarly or contain `word`,
+    /// with how much each should count.
+    fn similar_words<'a>(&'a self, word: &str) -> FxHashMap<&'a str, f32> {
+        let mut weights = FxHashMap::<&str, f32>::default();
+        let mut add_weight = |similar_word: &'a str, weight: f32| {
+            let best_weight = weights.entry(similar_word).or_insert(0.0);
+            *best_weight = best_weight.max(weight);
+        };
+        phonetic_key(word)
+            .and_then(|key| self.phonetic_indices.get(&key))
+            .into_iter()
+            .flatten()
+            .for_each(|similar_word| add_weight(similar_word, PHONETIC_MATCH_WEIGHT));
+        let length = word.chars().count();
+        if length >= MIN_FUZZY_WORD_LENGTH {
+            let max_distance = if length >= MIN_TWO_EDIT_WORD_LENGTH {
+                2
+            } else {
+                1
+            };
+            self.fuzzy_index
+                .find(word, max_distance)
+                .into_iter()
+                // The fuzzy index may still have words which are in no document.
+                .filter(|(similar_word, _)| self.live_words.contains_key(*similar_word))
+                .for_each(|(similar_word, distance)| {
+                    add_weight(similar_word, FUZZY_MATCH_WEIGHT.powi(distance as i32))
+                });
+        }
+        self.words_containing(word)
+            .into_iter()
+            .for_each(|(similar_word, weight)| add_weight(similar_word, weight));
+        weights
+    }
+
+    /// Returns the words in the index which contain `word`, and how much each should count.
+    fn words_containing<'a>(&'a self, word: &str) -> Vec<(&'a str, f32)> {
+        // Look up the rarest trigram first, and only keep words which have all the others.
+        let mut postings: Vec<&FxHashSet<Word>> = Vec::new();
+        for ngram in ngrams(word) {
+            match self.ngram_indices.get(&ngram) {
+                Some(words) => postings.push(words),
+                None => return vec![],
+            }
+        }
+        postings.sort_by_key(|words| words.len());
+        let (rarest, rest) = match postings.split_first() {
+            Some(split) => split,
+            None => return vec![],
+        };
+        let length = word.chars().count() as f32;
+        rarest
+            .iter()
+            .filter(|candidate| rest.iter().all(|words| words.contains(*candidate)))
+            .filter(|candidate| candidate.as_str() != word && candidate.contains(word))
+            .map(|candidate| {
+                let weight = if candidate.starts_with(word) {
+                    PREFIX_MATCH_WEIGHT
+                } else {
+                    SUBSTRING_MATCH_WEIGHT
+                };
+                (
+                    candidate.as_str(),
+                    weight * length / candidate.chars().count() as f32,
+                )
+            })
+            .collect()
+    }
+
+    /// Adds the document with the given text, replacing any document with the same id.
+    pub fn update(&mut self, document_id: DocumentId, document_content: &str) {
+        self.update_fields(document_id, std::iter::once((TEXT_FIELD, document_content)))
+    }
+
+    /// Adds the document with the given fields, replacing any document with the same id.
+    /// Phrases don't match across fields.
+    pub fn update_fields<'a>(
+        &mut self,
+        document_id: DocumentId,
+        fields: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) {
+        let fields = fields
+            .into_iter()
+            .map(|(field, content)| (field.to_string(), content.to_string()))
+            .collect();
+        self.index_fields(document_id, fields)
+    }
+
+    fn index_fields(&mut self, document_id: DocumentId, fields: Vec<(FieldName, String)>) {
+        let mut words = Vec::<(Word, f32)>::new();
+        // Words in any field but the href, which are the ones worth saying.
+        let mut said_words = FxHashSet::<Word>::default();
+        let mut positions = Vec::<Position>::new();
+        let mut next_position = 0;
+        let (mut text, mut href) = ("", "");
+        let phrases = alias_phrases(&self.aliases, &fields);
+        let fields_and_aliases = fields
+            .iter()
+            .map(|(field, content)| (field.as_str(), content.as_str()))
+            .chain(phrases.iter().map(|phrase| (ALIAS_FIELD, phrase.as_str())));
+        for (field, content) in fields_and_aliases {
+            match field {
+                TEXT_FIELD => text = content,
+                HREF_FIELD => href = content,
+                _ => {}
+            }
+            let field_weight = field_weight(&self.field_weights, field);
+            let field_words = tokenize(content);
+            if field != HREF_FIELD {
+                said_words.extend(field_words.iter().cloned());
+            }
+            positions.extend(next_position..next_position + field_words.len());
+            // Leave a gap between fields, so words from different fields are never close.
+            next_position += field_words.len() + FIELD_POSITION_GAP;
+            words.extend(field_words.into_iter().map(|word| (word, field_weight)));
+        }
+        let inverse_document_length: f32 = (words.len() as f32).recip();
+        // Only the fields are being updated, so the layout stays.
+        let layout = self.document_layouts.remove(&document_id);
+        self.remove(document_id);
+        if let Some(layout) = layout {
+            self.document_layouts.insert(document_id, layout);
+        }
+        self.document_links
+            .insert(document_id, LinkKey::new(text, href));
+        self.document_fields.insert(document_id, fields);
+        self.document_lengths.insert(document_id, words.len());
+        self.total_document_length += words.len();
+        let bag_of_words = self.document_bags.entry(document_id).or_default();
+        let mut new_words = Vec::<Word>::new();
+        for ((word, field_weight), position) in words.into_iter().zip(positions) {
+            // The index is keyed by terms, but we keep the words themselves in the bag, since
+            // those are what can be said.
+            let term = self.analysis.term(&word);
+            let word_index = self.word_indices.entry(term).or_insert_with(|| WordIndex {
+                frequency_by_document: FxHashMap::<DocumentId, f32>::default(),
+                positions_by_document: PositionsByDocument::default(),
+            });
+            let frequency_in_document = word_index
+                .frequency_by_document
+                .entry(document_id)
+                .or_insert(0.0);
+            *frequency_in_document += field_weight * inverse_document_length;
+            word_index
+                .positions_by_document
+                .entry(document_id)
+                .or_default()
+                .push(position);
+            if bag_of_words.insert(word.to_string()) {
+                new_words.push(word);
+            }
+        }
+        let unsaid_words: FxHashSet<Word> = new_words
+            .iter()
+            .filter(|word| !said_words.contains(*word))
+            .cloned()
+            .collect();
+        new_words.iter().for_each(|word| {
+            self.add_live_word(word);
+            if said_words.contains(word) {
+                self.add_vocabulary_word(word);
+            }
+        });
+        if !unsaid_words.is_empty() {
+            self.document_unsaid_words.insert(document_id, unsaid_words);
+        }
+        self.number_of_documents += 1
+    }
+
+    /// Counts another document containing `word`, adding it to the similar word indices if
+    /// it's the first. These are keyed by words rather than terms, since stems are often
+    /// too short to recognize typos or prefixes in.
+    fn add_live_word(&mut self, word: &str) {
+        let number_of_documents = self.live_words.entry(word.to_string()).or_insert(0);
+        *number_of_documents += 1;
+        if *number_of_documents > 1 {
+            return;
+        }
+        // This may be a word which died, but was never compacted away.
+        self.fuzzy_index.insert(word);
+        if let Some(key) = phonetic_key(word) {
+            self.phonetic_indices
+                .entry(key)
+                .or_default()
+                .insert(word.to_string());
+        }
+        for ngram in ngrams(word) {
+            self.ngram_indices
+                .entry(ngram)
+                .or_default()
+                .insert(word.to_string());
+        }
+    }
+
+    /// Counts one fewer document containing `word`, removing it from the similar word
+    /// indices if it was the last.
+    fn remove_live_word(&mut self, word: &str) {
+        match self.live_words.entry(word.to_string()) {
+            Entry::Occupied(mut entry) if *entry.get() > 1 => {
+                *entry.get_mut() -= 1;
+                return;
+            }
+            Entry::Occupied(entry) => {
+                entry.remove();
+            }
+            Entry::Vacant(_) => return,
+        }
+        if let Some(key) = phonetic_key(word) {
+            remove_word_from_set(&mut self.phonetic_indices, key, word);
+        }
+        for ngram in ngrams(word) {
+            remove_word_from_set(&mut self.ngram_indices, ngram, word);
+        }
+    }
+
+    /// Counts another document where `word` is said, telling Talon about it if it's the
+    /// first.
+    fn add_vocabulary_word(&mut self, word: &str) {
+        let number_of_documents = self.vocabulary.entry(word.to_string()).or_insert(0);
+        *number_of_documents += 1;
+        if *number_of_documents == 1 && is_sayable(word) {
+            let changes = &mut self.vocabulary_changes;
+            // A word which left and came back since Talon was last told cancels out.
+            if !changes.removed.remove(word) {
+                changes.added.insert(word.to_string());
+            }
+        }
+    }
+
+    /// Counts one fewer document where `word` is said, telling Talon it's gone if that
+    /// was the last.
+    fn remove_vocabulary_word(&mut self, word: &str) {
+        match self.vocabulary.entry(word.to_string()) {
+            Entry::Occupied(mut entry) if *entry.get() > 1 => {
+                *entry.get_mut() -= 1;
+                return;
+            }
+            Entry::Occupied(entry) => {
+                entry.remove();
+            }
+            Entry::Vacant(_) => return,
+        }
+        if is_sayable(word) {
+            let changes = &mut self.vocabulary_changes;
+            if !changes.added.remove(word) {
+                changes.removed.insert(word.to_string());
+            }
+        }
+    }
+
+    /// Rebuilds the fuzzy index once most of the words in it are dead, since words can't be
+    /// removed from it one at a time.
+    fn compact(&mut self) {
+        let dead_words = self.fuzzy_index.len() - self.live_words.len();
+        if dead_words <= self.live_words.len() {
+            return;
+        }
+        let mut fuzzy_index = BkTree::default();
+        self.live_words.keys().for_each(|word| {
+            fuzzy_index.insert(word);
+        });
+        self.fuzzy_index = fuzzy_index;
+    }
+
+    /// Removes the document, returning `None` if it wasn't in the index.
+    pub fn remove(&mut self, document_id: DocumentId) -> Option<()> {
+        // Single page apps churn through lots of unique words, so entries are removed as
+        // soon as no document has them, or we would slowly leak memory.
+        self.document_bags
+            .remove_entry(&document_id)
+            .map(|(_, bag)| {
+                self.number_of_documents -= 1;
+                let unsaid_words = self
+                    .document_unsaid_words
+                    .remove(&document_id)
+                    .unwrap_or_default();
+                self.document_links.remove(&document_id);
+                self.document_fields.remove(&document_id);
+                self.document_layouts.remove(&document_id);
+                self.total_document_length -=
+                    self.document_lengths.remove(&document_id).unwrap_or(0);
+                bag.iter().for_each(|word| {
+                    if let Entry::Occupied(mut entry) =
+                        self.word_indices.entry(self.analysis.term(word))
+                    {
+                        let word_index = entry.get_mut();
+                        word_index.frequency_by_document.remove_entry(&document_id);
+                        word_index.positions_by_document.remove_entry(&document_id);
+                        if word_index.frequency_by_document.is_empty() {
+                            entry.remove();
+                        }
+                    }
+                    self.remove_live_word(word);
+                    if !unsaid_words.contains(word) {
+                        self.remove_vocabulary_word(word);
+                    }
+                });
+                self.compact();
+            })
+    }
+
+    /// Sets where the document is on the page, which only changes how it ranks. Layouts
+    /// of documents which aren't in the index are ignored.
+    pub fn update_layout(&mut self, document_id: DocumentId, layout: Layout) {
+        if self.document_bags.contains_key(&document_id) {
+            self.document_layouts.insert(document_id, layout);
+        }
+    }
+
+    /// The documents which are on screen, from the top left of the page down.
+    pub fn visible_documents(&self) -> Vec<DocumentId> {
+        let mut visible: Vec<(&DocumentId, &Layout)> = self
+            .document_layouts
+            .iter()
+            .filter(|(_, layout)| layout.visible)
+            .collect();
+        visible.sort_by(|(id0, layout0), (id1, layout1)| {
+            (layout0.top, layout0.left)
+                .partial_cmp(&(layout1.top, layout1.left))
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(id0.cmp(id1))
+        });
+        visible.into_iter().map(|(&id, _)| id).collect()
+    }
+
+    /// Replaces the aliases the documents are indexed under, indexing the documents whose
+    /// aliases changed again.
+    pub fn set_aliases(&mut self, aliases: Vec<Alias>) {
+        if aliases == self.aliases {
+            return;
+        }
+        let old_aliases = std::mem::replace(&mut self.aliases, aliases);
+        let changed: Vec<DocumentId> = self
+            .document_fields
+            .iter()
+            .filter(|(_, fields)| {
+                alias_phrases(&old_aliases, fields) != alias_phrases(&self.aliases, fields)
+            })
+            .map(|(&document_id, _)| document_id)
+            .collect();
+        for document_id in changed {
+            if let Some(fields) = self.document_fields.get(&document_id).cloned() {
+                self.index_fields(document_id, fields);
+            }
+        }
+    }
+
+    /// The words in the index which can be said, leaving out those only in hrefs.
+    pub fn words(&self) -> FxHashSet<String> {
+        self.vocabulary
+            .keys()
+            .filter(|word| is_sayable(word))
+            .cloned()
+            .collect()
+    }
+
+    /// How to say the words in the index, mapped to the words themselves.
+    pub fn spoken_words(&self) -> SpokenForms {
+        spoken_form_map(self.vocabulary.keys().map(String::as_str))
+    }
+
+    /// Returns the spoken words added to and removed from the index since the last call.
+    pub fn take_vocabulary_changes(&mut self) -> VocabularyChanges {
+        std::mem::take(&mut self.vocabulary_changes)
+    }
+
+    /// Adds a document as the browser sent it, replacing any document with the same id.
+    pub fn update_with_content(
+        &mut self,
+        document_id: DocumentId,
+        document_content: &DocumentContent,
+    ) {
+        match document_content {
+            DocumentContent::Text(text) => self.update(document_id, text),
+            DocumentContent::Fields(fields) => {
+                // Sort the fields, so that positions don't depend on the hash order.
+                let mut fields: Vec<(&str, &str)> = fields
+                    .iter()
+                    .map(|(field, content)| (field.as_str(), content.as_str()))
+                    .collect();
+                fields.sort();
+                self.update_fields(document_id, fields)
+            }
+        }
+    }
+}
+
+// Snapshots come from disk, so a corrupt one mustn't make us allocate without bound. Pages
+// with tens of thousands of links have indices bigger than this, and aren't saved.
+const MAX_SNAPSHOT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The same encoding as `bincode::serialize`, but limited in size.
+fn snapshot_options() -> impl bincode::Options {
+    bincode::options()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(MAX_SNAPSHOT_SIZE)
+}
+
+/// Whether `word` has any spoken form. Words without one are still searched, but Talon
+/// isn't told about them.
+fn is_sayable(word: &str) -> bool {
+    !spoken_forms(word).is_empty()
+}
+
+/// Combines the scores of alternative queries, each weighted by how likely it is. The
+/// weights are normalized over the alternatives which matched anything, so when only
+/// an unlikely alternative matches, its results count fully rather than being dwarfed
+/// by likelier alternatives which matched nothing.
+pub fn combine_scores(scores: impl IntoIterator<Item = (ScoreResult, f32)>) -> ScoreResult {
+    let mut combined = ScoreResult::default();
+    let mut total_weight = 0.0;
+    for (scores, weight) in scores {
+        if scores.is_empty() || weight <= 0.0 {
+            continue;
+        }
+        total_weight += weight;
+        scores.into_iter().for_each(|(document_id, score)| {
+            *combined.entry(document_id).or_insert(0.0) += weight * score;
+        });
+    }
+    combined
+        .values_mut()
+        .for_each(|score| *score /= total_weight);
+    combined
+}
+
+/// The phrases of the aliases which match the document with these fields.
+fn alias_phrases(aliases: &[Alias], fields: &[(FieldName, String)]) -> Vec<String> {
+    if aliases.is_empty() {
+        return vec![];
+    }
+    let fields: Vec<(&str, &str)> = fields
+        .iter()
+        .map(|(field, content)| (field.as_str(), content.as_str()))
+        .collect();
+    aliases
+        .iter()
+        .filter(|alias| alias.matches(&fields))
+        .map(|alias| alias.say.clone())
+        .collect()
+}
+
+/// How much to multiply a document's score by, based on how many of the query words
+/// it contains, and whether they appear next to each other in the same order.
+fn phrase_bonus(matches: &[PositionsByDocument], document_id: DocumentId) -> f32 {
+    let matched = matches
+        .iter()
+        .filter(|positions| positions.contains_key(&document_id))
+        .count();
+    let coverage = matched as f32 / matches.len() as f32;
+    let proximity = matches
+        .windows(2)
+        .map(
+            |pair| match (pair[0].get(&document_id), pair[1].get(&document_id)) {
+                (Some(first), Some(second)) => closeness(first, second),
+                _ => 0.0,
+            },
+        )
+        .sum::<f32>()
+        / (matches.len() - 1) as f32;
+    1.0 + COVERAGE_BONUS * coverage + PROXIMITY_BONUS * proximity
+}
+
+/// 1 when a word at one of the `second` positions directly follows a word at one of the
+/// `first` positions, falling off with the distance between them. Words in the wrong
+/// order count as further apart.
+fn closeness(first: &[Position], second: &[Position]) -> f32 {
+    first
+        .iter()
+        .cartesian_product(second)
+        .map(|(&a, &b)| if b > a { b - a } else { a - b + 2 })
+        .min()
+        .map_or(0.0, |distance| (distance as f32).recip())
+}
+
+fn remove_word_from_set<K: std::hash::Hash + Eq>(
+    sets: &mut FxHashMap<K, FxHashSet<Word>>,
+    key: K,
+    word: &str,
+) {
+    if let Entry::Occupied(mut entry) = sets.entry(key) {
+        entry.get_mut().remove(word);
+        if entry.get().is_empty() {
+            entry.remove();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_index(documents: Vec<(DocumentId, String)>) -> TfidfIndex {
+        make_index_with_config(&Config::default(), documents)
+    }
+
+    fn make_index_with_config(config: &Config, documents: Vec<(DocumentId, String)>) -> TfidfIndex {
+        let mut tfidf_index = TfidfIndex::new(config);
+        documents
+            .iter()
+            .for_each(|(document_id, document)| tfidf_index.update(*document_id, document));
+        tfidf_index
+    }
+
+    #[test]
+    fn create_index_and_query() {
+        let documents: Vec<String> = vec![
+            String::from("this is sample").to_string(),
+            String::from("this is another another example example example").to_string(),
+        ];
+
+        let tfidf_index: TfidfIndex = make_index(
+            documents
+                .iter()
+                .enumerate()
+                .map(|(id, doc)| (id, doc.to_string()))
+                .collect(),
+        );
+
+        // Words in every document still match, but count for most in short ones.
+        let scores_this_is = tfidf_index.score("this is");
+        assert!(scores_this_is[&0] > scores_this_is[&1]);
+        assert!(scores_this_is[&1] > 0.0);
+
+        // "example" is 3 of the 7 words of document 1, which is one of two documents:
+        // 3/7 * ln(1 + 2/1).
+        let scores_example = tfidf_index.score("example");
+        assert_eq!(scores_example.get(&0).cloned().unwrap_or(0.0), 0.0);
+        assert_eq!(scores_example.get(&1).cloned().unwrap_or(0.0), 0.4708339);
+    }
+
+    #[test]
+    fn create_index_and_update() {
+        let documents: Vec<String> = vec![
+            String::from("this is sample").to_string(),
+            String::from("this will get overwritten").to_string(),
+        ];
+
+        let mut tfidf_index: TfidfIndex = make_index(
+            documents
+                .iter()
+                .enumerate()
+                .map(|(id, doc)| (id, doc.to_string()))
+                .collect(),
+        );
+
+        tfidf_index.update(1, &String::from("will be overwrriten this is").to_string());
+        tfidf_index.update(
+            1,
+            &String::from("will be overwrriten this is example").to_string(),
+        );
+        tfidf_index.update(
+            1,
+            &String::from("this is another another example example example").to_string(),
+        );
+
+        // Words in every document still match, but count for most in short ones.
+        let scores_this_is = tfidf_index.score("this is");
+        assert!(scores_this_is[&0] > scores_this_is[&1]);
+        assert!(scores_this_is[&1] > 0.0);
+
+        // "example" is 3 of the 7 words of document 1, which is one of two documents:
+        // 3/7 * ln(1 + 2/1).
+        let scores_example = tfidf_index.score("example");
+        assert_eq!(scores_example.get(&0).cloned().unwrap_or(0.0), 0.0);
+        assert_eq!(scores_example.get(&1).cloned().unwrap_or(0.0), 0.4708339);
+    }
+
+    #[test]
+    fn query_matches_homophones() {
+        let tfidf_index: TfidfIndex = make_index(vec![
+            (0, "site map".to_string()),
+            (1, "four seasons".to_string()),
+            (2, "contact us".to_string()),
+        ]);
+
+        let scores_sight = tfidf_index.score("sight");
+        let scores_site = tfidf_index.score("site");
+        assert!(scores_sight.get(&0).cloned().unwrap_or(0.0) > 0.0);
+        assert!(scores_sight[&0] < scores_site[&0]);
+        assert_eq!(scores_sight.get(&1), None);
+
+        let scores_for = tfidf_index.score("for seasons");
+        assert!(scores_for[&1] > tfidf_index.score("seasons")[&1]);
+    }
+
+    #[test]
+    fn numbers_are_indexed_as_words() {
+        let tfidf_index: TfidfIndex = make_index(vec![
+            (0, "Page 2".to_string()),
+            (1, "Page 3".to_string()),
+            (2, "Download MP3".to_string()),
+        ]);
+
+        let scores = tfidf_index.score("two");
+        assert!(scores.get(&0).cloned().unwrap_or(0.0) > 0.0);
+        assert_eq!(scores.get(&1), None);
+        assert!(tfidf_index.score("three")[&2] > 0.0);
+        assert!(tfidf_index.score("mp3")[&2] > 0.0);
+
+        let words = tfidf_index.words();
+        assert!(words.contains("two"));
+        assert!(words.contains("mp"));
+        // "mp3" can be said as "em pee three".
+        assert!(words.contains("mp3"));
+    }
+
+    #[test]
+    fn compound_words_match_their_parts() {
+        let tfidf_index: TfidfIndex = make_index(vec![
+            (0, "GitHub".to_string()),
+            (1, "GitLab".to_string()),
+            (2, "Hub Spot".to_string()),
+        ]);
+
+        let scores_joined = tfidf_index.score("github");
+        assert!(scores_joined.get(&0).cloned().unwrap_or(0.0) > 0.0);
+        let scores_split = tfidf_index.score("git hub");
+        assert!(scores_split[&0] > scores_split[&1]);
+        assert!(scores_split[&0] > scores_split[&2]);
+    }
+
+    #[test]
+    fn accents_and_case_are_folded() {
+        let tfidf_index: TfidfIndex = make_index(vec![
+            (0, "Café".to_string()),
+            (1, "Über uns".to_string()),
+            (2, "Kontakt".to_string()),
+        ]);
+
+        assert!(tfidf_index.score("cafe")[&0] > 0.0);
+        assert!(tfidf_index.score("CAFÉ")[&0] > 0.0);
+        assert!(tfidf_index.score("über")[&1] > 0.0);
+        assert!(!tfidf_index.words().contains(""));
+    }
+
+    #[test]
+    fn bm25_prefers_short_documents() {
+        let documents = vec![
+            (0, "Settings".to_string()),
+            (
+                1,
+                "Open the settings page to change how the account works".to_string(),
+            ),
+            (2, "Help".to_string()),
+        ];
+        let config = Config {
+            ranking: Ranking::Bm25 { k1: 1.2, b: 0.75 },
+            ..Config::default()
+        };
+        let tfidf_index: TfidfIndex = make_index_with_config(&config, documents);
+
+        let scores = tfidf_index.score("settings");
+        assert!(scores[&0] > scores[&1]);
+        assert!(scores[&1] > 0.0);
+        assert_eq!(scores.get(&2), None);
+    }
+
+    #[test]
+    fn bm25_saturates_repeated_words() {
+        let config = Config {
+            ranking: Ranking::Bm25 { k1: 1.2, b: 0.0 },
+            ..Config::default()
+        };
+        let tfidf_index: TfidfIndex = make_index_with_config(
+            &config,
+            vec![
+                (0, "news".to_string()),
+                (1, "news news news news news news".to_string()),
+                (2, "sports".to_string()),
+            ],
+        );
+
+        let scores = tfidf_index.score("news");
+        assert!(scores[&1] > scores[&0]);
+        assert!(scores[&1] < 2.0 * scores[&0]);
+    }
+
+    #[test]
+    fn query_matches_typos() {
+        let tfidf_index: TfidfIndex = make_index(vec![
+            (0, "Account settings".to_string()),
+            (1, "Notifications".to_string()),
+            (2, "Log out".to_string()),
+        ]);
+
+        let scores_typo = tfidf_index.score("setings");
+        let scores_exact = tfidf_index.score("settings");
+        assert!(scores_typo.get(&0).cloned().unwrap_or(0.0) > 0.0);
+        assert!(scores_typo[&0] < scores_exact[&0]);
+        assert!(tfidf_index.score("notificatoins")[&1] > 0.0);
+        // Short words are too ambiguous to correct.
+        assert_eq!(tfidf_index.score("lob").get(&2), None);
+    }
+
+    #[test]
+    fn phrases_beat_scattered_words() {
+        let tfidf_index: TfidfIndex = make_index(vec![
+            (0, "privacy policy".to_string()),
+            (1, "policy privacy".to_string()),
+            (2, "the privacy of users is our policy".to_string()),
+            (3, "privacy".to_string()),
+            (4, "policy".to_string()),
+            (5, "cookies".to_string()),
+        ]);
+
+        let scores = tfidf_index.score("privacy policy");
+        assert!(scores[&0] > scores[&1]);
+        assert!(scores[&1] > scores[&2]);
+        assert!(scores[&0] > scores[&3]);
+        assert!(scores[&0] > scores[&4]);
+    }
+
+    #[test]
+    fn phrases_count_when_every_document_has_the_words() {
+        let tfidf_index: TfidfIndex = make_index(vec![
+            (0, "Sign in".to_string()),
+            (1, "in order to sign up".to_string()),
+        ]);
+
+        let scores = tfidf_index.score("sign in");
+        assert!(scores[&1] > 0.0);
+        assert!(scores[&0] > 1.5 * scores[&1]);
+
+        // Only the order of the words tells these apart.
+        let tfidf_index: TfidfIndex = make_index(vec![
+            (0, "Sign in".to_string()),
+            (1, "in, sign".to_string()),
+        ]);
+        let scores = tfidf_index.score("sign in");
+        assert!(scores[&0] > scores[&1]);
+    }
+
+    #[test]
+    fn query_matches_prefixes_and_substrings() {
+        let tfidf_index: TfidfIndex = make_index(vec![
+            (0, "Documentation".to_string()),
+            (1, "Configuration".to_string()),
+            (2, "Docker".to_string()),
+        ]);
+
+        let scores_docu = tfidf_index.score("docu");
+        assert!(scores_docu.get(&0).cloned().unwrap_or(0.0) > 0.0);
+        assert_eq!(scores_docu.get(&2), None);
+
+        let scores_config = tfidf_index.score("config");
+        let scores_figur = tfidf_index.score("figur");
+        let scores_exact = tfidf_index.score("configuration");
+        assert!(scores_figur[&1] > 0.0);
+        assert!(scores_figur[&1] < scores_config[&1]);
+        assert!(scores_config[&1] < scores_exact[&1]);
+    }
+
+    #[test]
+    fn fields_are_indexed_with_weights() {
+        let mut tfidf_index = make_index(vec![(2, "Help".to_string())]);
+        let fields = |fields: &[(&str, &str)]| {
+            DocumentContent::Fields(
+                fields
+                    .iter()
+                    .map(|&(field, content)| (field.to_string(), content.to_string()))
+                    .collect(),
+            )
+        };
+        tfidf_index.update_with_content(0, &fields(&[("text", "×"), ("ariaLabel", "Close")]));
+        tfidf_index.update_with_content(1, &fields(&[("text", "Done"), ("href", "/close")]));
+
+        let scores = tfidf_index.score("close");
+        assert!(scores[&0] > scores[&1]);
+        assert!(scores[&1] > 0.0);
+    }
+
+    #[test]
+    fn words_are_stemmed() {
+        let tfidf_index: TfidfIndex = make_index(vec![
+            (0, "Setting".to_string()),
+            (1, "Downloads".to_string()),
+            (2, "Help".to_string()),
+        ]);
+
+        assert!(tfidf_index.score("settings")[&0] > 0.0);
+        assert!(tfidf_index.score("download")[&1] > 0.0);
+        let words = tfidf_index.words();
+        assert!(words.contains("setting"));
+        assert!(words.contains("downloads"));
+    }
+
+    #[test]
+    fn stop_words_count_for_little() {
+        let tfidf_index: TfidfIndex =
+            make_index(vec![(0, "The Terms".to_string()), (1, "Help".to_string())]);
+
+        let scores_the = tfidf_index.score("the");
+        let scores_terms = tfidf_index.score("terms");
+        assert!(scores_the[&0] > 0.0);
+        assert!(scores_the[&0] < scores_terms[&0]);
+        assert!(tfidf_index.words().contains("the"));
+    }
+
+    #[test]
+    fn snapshots_restore_the_index() {
+        let config = Config::default();
+        let mut tfidf_index = make_index(vec![
+            (0, "Inbox".to_string()),
+            (1, "Sent mail".to_string()),
+            (2, "Notifications".to_string()),
+        ]);
+        tfidf_index.remove(1);
+        let mut restored =
+            TfidfIndex::from_snapshot(&config, &tfidf_index.snapshot().unwrap()).unwrap();
+
+        assert_eq!(index_size(&restored), index_size(&tfidf_index));
+        assert_eq!(restored.words(), tfidf_index.words());
+        assert_eq!(
+            restored.score("notificatoins"),
+            tfidf_index.score("notificatoins")
+        );
+        assert_eq!(restored.score("mail").get(&1), None);
+        restored.update(1, "Drafts");
+        assert!(restored.score("drafts")[&1] > 0.0);
+
+        let mut other_config = Config::default();
+        other_config.analysis.stemmer = None;
+        assert!(
+            TfidfIndex::from_snapshot(&other_config, &tfidf_index.snapshot().unwrap()).is_none()
+        );
+        assert!(TfidfIndex::from_snapshot(&config, b"garbage").is_none());
+    }
+
+    #[test]
+    fn bad_snapshots_are_rejected() {
+        let config = Config::default();
+        let mut tfidf_index = make_index(vec![(0, "Inbox".to_string())]);
+        let snapshot = tfidf_index.snapshot().unwrap();
+        assert!(TfidfIndex::from_snapshot(&config, &snapshot[..snapshot.len() / 2]).is_none());
+        // A length far beyond the size limit, where the document bags start.
+        let mut huge = snapshot.clone();
+        huge[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(TfidfIndex::from_snapshot(&config, &huge).is_none());
+
+        tfidf_index.document_lengths.clear();
+        assert!(TfidfIndex::from_snapshot(&config, &tfidf_index.snapshot().unwrap()).is_none());
+    }
+
+    #[test]
+    fn oversized_snapshots_are_not_made() {
+        let mut tfidf_index = make_index(vec![(0, "Inbox".to_string())]);
+        // Far quicker than indexing enough links to get this big.
+        let huge_word = "a".repeat(MAX_SNAPSHOT_SIZE as usize);
+        tfidf_index
+            .document_unsaid_words
+            .insert(0, std::iter::once(huge_word).collect());
+        assert!(tfidf_index.snapshot().is_err());
+    }
+
+    #[test]
+    fn clicked_links_are_boosted() {
+        let mut tfidf_index = make_index(vec![(0, "Help".to_string())]);
+        let mut fields = FxHashMap::default();
+        fields.insert("text".to_string(), "Inbox (3)".to_string());
+        fields.insert("href".to_string(), "/inbox".to_string());
+        tfidf_index.update_with_content(1, &DocumentContent::Fields(fields));
+        tfidf_index.update(2, "Index");
+        let inbox = tfidf_index.link(1).unwrap().clone();
+        assert_eq!(inbox, LinkKey::new("Inbox (3)", "/inbox"));
+
+        let mut boosts = FxHashMap::default();
+        boosts.insert(inbox, 0.25);
+        assert_eq!(tfidf_index.score("in").get(&1), None);
+        assert_eq!(tfidf_index.score_with_boosts("in", &boosts)[&1], 0.25);
+        let scores = tfidf_index.score("index");
+        let boosted_scores = tfidf_index.score_with_boosts("index", &boosts);
+        assert_eq!(boosted_scores[&2], scores[&2]);
+        assert_eq!(
+            boosted_scores[&1],
+            scores.get(&1).cloned().unwrap_or(0.0) + 0.25
+        );
+    }
+
+    #[test]
+    fn aliases_are_indexed_and_can_change() {
+        let mut tfidf_index = make_index(vec![(0, "»".to_string()), (1, "Past".to_string())]);
+        tfidf_index.take_vocabulary_changes();
+        let next = Alias {
+            say: "next page".to_string(),
+            text: Some("»".to_string()),
+            ..Alias::default()
+        };
+        tfidf_index.set_aliases(vec![next.clone()]);
+        assert!(tfidf_index.score("next page")[&0] > 0.0);
+        let changes = tfidf_index.take_vocabulary_changes();
+        assert!(changes.added.contains("next") && changes.added.contains("page"));
+
+        // Documents added later get the aliases too, and keep them in snapshots.
+        tfidf_index.update(2, "»");
+        let mut restored =
+            TfidfIndex::from_snapshot(&Config::default(), &tfidf_index.snapshot().unwrap())
+                .unwrap();
+        assert_eq!(restored.score("next page").len(), 2);
+
+        restored.set_aliases(vec![]);
+        assert!(restored.score("next page").is_empty());
+        assert!(!restored.words().contains("next"));
+        assert_eq!(restored.score("past").len(), 1);
+    }
+
+    #[test]
+    fn layouts_favor_visible_large_and_high_documents() {
+        let mut tfidf_index = make_index(vec![
+            (0, "Settings".to_string()),
+            (1, "Settings".to_string()),
+            (2, "Settings".to_string()),
+            (3, "Help".to_string()),
+        ]);
+        let scores = tfidf_index.score("settings");
+        assert_eq!(scores[&0], scores[&1]);
+
+        let layout = Layout {
+            visible: false,
+            top: 2000.0,
+            left: 0.0,
+            width: 50.0,
+            height: 20.0,
+            font_size: 16.0,
+        };
+        tfidf_index.update_layout(0, layout);
+        tfidf_index.update_layout(
+            1,
+            Layout {
+                visible: true,
+                ..layout
+            },
+        );
+        let scores = tfidf_index.score("settings");
+        assert!(scores[&1] > 1.5 * scores[&0]);
+
+        let layout = Layout {
+            visible: true,
+            ..layout
+        };
+        let bigger = Layout {
+            font_size: 24.0,
+            ..layout
+        };
+        let higher = Layout { top: 0.0, ..layout };
+        assert!(bigger.prior() > layout.prior());
+        assert!(higher.prior() > layout.prior());
+
+        // Updating the text keeps the layout, but removing the document drops it.
+        tfidf_index.update(1, "Settings");
+        assert_eq!(tfidf_index.score("settings")[&1], scores[&1]);
+        tfidf_index.remove(1);
+        tfidf_index.update(1, "Settings");
+        assert_eq!(tfidf_index.score("settings")[&1], scores[&2]);
+        tfidf_index.update_layout(9, layout);
+        assert!(!tfidf_index.document_layouts.contains_key(&9));
+
+        tfidf_index.update_layout(2, higher);
+        tfidf_index.update_layout(1, layout);
+        assert_eq!(tfidf_index.visible_documents(), vec![2, 1]);
+    }
+
+    #[test]
+    fn alternatives_which_match_win() {
+        let tfidf_index = make_index(vec![
+            (0, "Inbox".to_string()),
+            (1, "Sent mail".to_string()),
+            (2, "Drafts".to_string()),
+        ]);
+        let alternatives = |weights: &[(&str, f32)]| -> Vec<(String, f32)> {
+            weights
+                .iter()
+                .map(|&(query, weight)| (query.to_string(), weight))
+                .collect()
+        };
+
+        // The likeliest hearing matches nothing, so the next one counts fully.
+        let scores = tfidf_index
+            .score_alternatives(&alternatives(&[("zebra crossing", 0.9), ("drafts", 0.1)]));
+        assert_eq!(scores.len(), 1);
+        assert_eq!(scores[&2], tfidf_index.score("drafts")[&2]);
+
+        let scores =
+            tfidf_index.score_alternatives(&alternatives(&[("inbox", 0.75), ("drafts", 0.25)]));
+        assert!((scores[&0] - 0.75 * tfidf_index.score("inbox")[&0]).abs() < 1e-6);
+        assert!(scores[&0] > scores[&2]);
+        assert!(tfidf_index.score_alternatives(&[]).is_empty());
+    }
+
+    #[test]
+    fn alternatives_are_boosted_by_their_own_clicks() {
+        let mut tfidf_index = make_index(vec![(0, "Inbox".to_string())]);
+        tfidf_index.update(1, "Drafts");
+        let drafts = tfidf_index.link(1).unwrap().clone();
+        let alternatives = vec![("zebra".to_string(), 0.6), ("drafts".to_string(), 0.4)];
+        let boosts = |query: &str| {
+            let mut boosts = FxHashMap::default();
+            if query == "drafts" {
+                boosts.insert(drafts.clone(), 0.25);
+            }
+            boosts
+        };
+
+        let (scores, likeliest) = tfidf_index.score_alternatives_with_boosts(&alternatives, boosts);
+        assert_eq!(scores[&1], tfidf_index.score("drafts")[&1] + 0.25);
+        assert_eq!(likeliest, Some("drafts"));
+        let (scores, likeliest) =
+            tfidf_index.score_alternatives_with_boosts(&alternatives[..1], boosts);
+        assert!(scores.is_empty());
+        assert_eq!(likeliest, None);
+    }
+
+    fn index_size(tfidf_index: &TfidfIndex) -> Vec<usize> {
+        vec![
+            tfidf_index.number_of_documents,
+            tfidf_index.document_bags.len(),
+            tfidf_index.document_unsaid_words.len(),
+            tfidf_index.document_lengths.len(),
+            tfidf_index.total_document_length,
+            tfidf_index.document_links.len(),
+            tfidf_index.document_fields.len(),
+            tfidf_index.document_layouts.len(),
+            tfidf_index.word_indices.len(),
+            tfidf_index.live_words.len(),
+            tfidf_index.vocabulary.len(),
+            tfidf_index.phonetic_indices.len(),
+            tfidf_index.ngram_indices.len(),
+        ]
+    }
+
+    #[test]
+    fn index_size_returns_to_baseline_after_churn() {
+        let mut tfidf_index: TfidfIndex =
+            make_index(vec![(0, "Inbox".to_string()), (1, "Sent mail".to_string())]);
+        let baseline = index_size(&tfidf_index);
+        let baseline_fuzzy_index_len = tfidf_index.fuzzy_index.len();
+
+        for round in 0..100 {
+            (0..10).for_each(|i| {
+                let id = 100 + i;
+                let text = format!("Message {} from sender{}x{} about inbox", id, round, i);
+                tfidf_index.update(id, &text);
+            });
+            (0..10).for_each(|i| {
+                tfidf_index.remove(100 + i).unwrap();
+            });
+        }
+
+        assert_eq!(index_size(&tfidf_index), baseline);
+        assert!(tfidf_index.fuzzy_index.len() <= 2 * baseline_fuzzy_index_len);
+        assert!(tfidf_index.score("inbox")[&0] > 0.0);
+        assert_eq!(tfidf_index.score("message").get(&100), None);
+    }
+
+    fn words(words: &[&str]) -> FxHashSet<Word> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn href_words_are_searched_but_not_said() {
+        let mut tfidf_index = make_index(vec![(0, "Go".to_string())]);
+        tfidf_index.update_fields(
+            1,
+            vec![
+                ("text", "Never gonna give you up"),
+                (
+                    "href",
+                    "https://www.youtube.com/watch?v=dQw4w9WgXcQ&utm_source=feed&sid=8f3a9c2e71",
+                ),
+            ],
+        );
+        assert!(tfidf_index.score("youtube")[&1] > 0.0);
+
+        let spoken_words = tfidf_index.spoken_words();
+        assert_eq!(spoken_words["gonna"], "gonna");
+        let written: FxHashSet<&str> = spoken_words.values().map(String::as_str).collect();
+        assert_eq!(
+            written,
+            ["go", "never", "gonna", "give", "you", "up"]
+                .iter()
+                .cloned()
+                .collect()
+        );
+        assert!(tfidf_index
+            .take_vocabulary_changes()
+            .added
+            .iter()
+            .all(|word| written.contains(word.as_str())));
+
+        let restored =
+            TfidfIndex::from_snapshot(&Config::default(), &tfidf_index.snapshot().unwrap())
+                .unwrap();
+        assert_eq!(restored.spoken_words(), spoken_words);
+
+        // Words in the text as well as the href are still said, until the text goes.
+        tfidf_index.update_fields(
+            1,
+            vec![("text", "YouTube"), ("href", "https://youtube.com/")],
+        );
+        assert_eq!(tfidf_index.spoken_words()["youtube"], "youtube");
+        assert!(tfidf_index
+            .take_vocabulary_changes()
+            .added
+            .contains("youtube"));
+        tfidf_index.update_fields(1, vec![("text", "Home"), ("href", "https://youtube.com/")]);
+        assert!(!tfidf_index.spoken_words().contains_key("youtube"));
+        assert!(tfidf_index
+            .take_vocabulary_changes()
+            .removed
+            .contains("youtube"));
+    }
+
+    #[test]
+    fn words_are_mapped_from_how_they_are_said() {
+        let tfidf_index = make_index(vec![
+            (0, "k8s PRs in C++".to_string()),
+            (1, "Go".to_string()),
+        ]);
+        let spoken_words = tfidf_index.spoken_words();
+        assert_eq!(spoken_words["kubernetes"], "k8s");
+        assert_eq!(spoken_words["kay eight ess"], "k8s");
+        assert_eq!(spoken_words["pull requests"], "prs");
+        assert_eq!(spoken_words["see plus plus"], "c++");
+        assert_eq!(spoken_words["in"], "in");
+        assert!(tfidf_index.score(&spoken_words["see plus plus"])[&0] > 0.0);
+    }
+
+    #[test]
+    fn vocabulary_changes_are_tracked() {
+        let mut tfidf_index: TfidfIndex = make_index(vec![
+            (0, "Inbox drafts".to_string()),
+            (1, "Sent drafts 42".to_string()),
+        ]);
+        assert_eq!(
+            tfidf_index.take_vocabulary_changes(),
+            VocabularyChanges {
+                added: words(&["inbox", "drafts", "sent", "forty", "two"]),
+                removed: words(&[]),
+            }
+        );
+        assert_eq!(
+            tfidf_index.words(),
+            words(&["inbox", "drafts", "sent", "forty", "two"])
+        );
+
+        // "drafts" is still in document 1, and "inbox" comes straight back.
+        tfidf_index.update(0, "Spam");
+        tfidf_index.update(2, "Inbox");
+        assert_eq!(
+            tfidf_index.take_vocabulary_changes(),
+            VocabularyChanges {
+                added: words(&["spam"]),
+                removed: words(&[]),
+            }
+        );
+
+        tfidf_index.remove(1);
+        tfidf_index.update(3, "Trash");
+        tfidf_index.remove(3);
+        assert_eq!(
+            tfidf_index.take_vocabulary_changes(),
+            VocabularyChanges {
+                added: words(&[]),
+                removed: words(&["drafts", "sent", "forty", "two"]),
+            }
+        );
+        assert!(tfidf_index.take_vocabulary_changes().is_empty());
+    }
+}
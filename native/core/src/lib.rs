@@ -0,0 +1,40 @@
+//! The ranking engine and protocol types behind voicesurf.
+//!
+//! A [`TfidfIndex`] holds the clickable elements of one page as documents, and scores
+//! them against what the user said:
+//!
+//! ```
+//! use voicesurf_core::config::Config;
+//! use voicesurf_core::TfidfIndex;
+//!
+//! let mut index = TfidfIndex::new(&Config::default());
+//! index.update(0, "Sign in");
+//! index.update(1, "Create account");
+//! let scores = index.score("sign in");
+//! assert!(scores[&0] > scores.get(&1).cloned().unwrap_or(0.0));
+//! ```
+//!
+//! The [`protocol`] module has the messages the native host exchanges with the browser
+//! extension and the Talon script, and the [`frame`] module how they are framed on the
+//! Talon socket.
+#![warn(missing_docs)]
+#[macro_use]
+extern crate serde_derive;
+
+pub mod aliases;
+pub mod analysis;
+pub mod atomic;
+pub mod config;
+pub mod frame;
+mod fuzzy;
+pub mod hints;
+pub mod history;
+pub mod index;
+mod ngram;
+mod phonetic;
+pub mod protocol;
+pub mod snapshot;
+pub mod spoken;
+pub mod tokenizer;
+
+pub use index::{DocumentContent, DocumentId, Layout, Score, ScoreResult, TfidfIndex};
@@ -0,0 +1,30 @@
+//! Character trigrams, for finding the index words which contain a query word, so that
+//! "docu" matches "documentation" and "config" matches "configuration".
+
+pub const NGRAM_LENGTH: usize = 3;
+
+/// Returns the distinct character trigrams in `word`. Words shorter than a trigram
+/// have none.
+pub fn ngrams(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut ngrams: Vec<String> = chars
+        .windows(NGRAM_LENGTH)
+        .map(|ngram| ngram.iter().collect())
+        .collect();
+    ngrams.sort();
+    ngrams.dedup();
+    ngrams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_words_into_trigrams() {
+        assert_eq!(ngrams("docs"), vec!["doc", "ocs"]);
+        assert_eq!(ngrams("aaaa"), vec!["aaa"]);
+        assert_eq!(ngrams("café"), vec!["afé", "caf"]);
+        assert_eq!(ngrams("go"), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,175 @@
+//! A small Metaphone-style phonetic encoder. Speech recognition engines regularly
+//! hear the wrong homophone ("for" instead of "four", "sight" instead of "site"),
+//! so we key words by how they sound in addition to how they are spelled.
+//!
+//! This follows the rules of Lawrence Philips' original Metaphone, which is much
+//! smaller than Double Metaphone and good enough for short English link texts.
+
+fn is_vowel(ch: u8) -> bool {
+    matches!(ch, b'A' | b'E' | b'I' | b'O' | b'U')
+}
+
+/// Returns the phonetic key of `word`, or `None` if the word has no letters we
+/// know how to encode.
+pub fn phonetic_key(word: &str) -> Option<String> {
+    let mut letters: Vec<u8> = word
+        .bytes()
+        .filter(u8::is_ascii_alphabetic)
+        .map(|ch| ch.to_ascii_uppercase())
+        .collect();
+    // Adjacent duplicate letters sound the same, except for C ("accent").
+    letters.dedup_by(|a, b| a == b && *a != b'C');
+    if letters.is_empty() {
+        return None;
+    }
+
+    // Some initial letter pairs have a silent first letter.
+    match (letters[0], letters.get(1).cloned()) {
+        (b'K', Some(b'N'))
+        | (b'G', Some(b'N'))
+        | (b'P', Some(b'N'))
+        | (b'A', Some(b'E'))
+        | (b'W', Some(b'R')) => {
+            letters.remove(0);
+        }
+        (b'X', _) => letters[0] = b'S',
+        (b'W', Some(b'H')) => {
+            letters.remove(1);
+        }
+        _ => {}
+    }
+
+    let at = |i: usize| -> u8 { letters.get(i).cloned().unwrap_or(0) };
+    let mut key = String::new();
+    for i in 0..letters.len() {
+        let ch = letters[i];
+        let previous = if i > 0 { at(i - 1) } else { 0 };
+        let next = at(i + 1);
+        let after_next = at(i + 2);
+        let is_last = i + 1 == letters.len();
+        match ch {
+            b'A' | b'E' | b'I' | b'O' | b'U' => {
+                // Vowels only matter at the start of a word, and all sound alike.
+                if i == 0 {
+                    key.push('A');
+                }
+            }
+            b'B' => {
+                // "dumb", "climb"
+                if !(previous == b'M' && is_last) {
+                    key.push('B');
+                }
+            }
+            b'C' => {
+                if next == b'I' && after_next == b'A' {
+                    key.push('X');
+                } else if next == b'H' {
+                    key.push(if previous == b'S' { 'K' } else { 'X' });
+                } else if matches!(next, b'I' | b'E' | b'Y') {
+                    if previous != b'S' {
+                        key.push('S');
+                    }
+                } else {
+                    key.push('K');
+                }
+            }
+            b'D' => {
+                if next == b'G' && matches!(after_next, b'E' | b'Y' | b'I') {
+                    key.push('J');
+                } else {
+                    key.push('T');
+                }
+            }
+            b'G' => {
+                let silent_gh = next == b'H' && !(i + 2 < letters.len() && is_vowel(after_next));
+                let silent_gn = next == b'N'
+                    && (i + 2 == letters.len()
+                        || (after_next == b'E' && at(i + 3) == b'D' && i + 4 == letters.len()));
+                if silent_gh || silent_gn {
+                    // "sight", "sign", "signed"
+                } else if matches!(next, b'I' | b'E' | b'Y') && previous != b'G' {
+                    key.push('J');
+                } else {
+                    key.push('K');
+                }
+            }
+            b'H' => {
+                let after_modifier = matches!(previous, b'C' | b'S' | b'P' | b'T' | b'G');
+                let between_vowel = is_vowel(previous) && !is_vowel(next);
+                if !after_modifier && !between_vowel {
+                    key.push('H');
+                }
+            }
+            b'K' => {
+                if previous != b'C' {
+                    key.push('K');
+                }
+            }
+            b'P' => key.push(if next == b'H' { 'F' } else { 'P' }),
+            b'Q' => key.push('K'),
+            b'S' => {
+                if next == b'H' || (next == b'I' && matches!(after_next, b'O' | b'A')) {
+                    key.push('X');
+                } else {
+                    key.push('S');
+                }
+            }
+            b'T' => {
+                if next == b'I' && matches!(after_next, b'O' | b'A') {
+                    key.push('X');
+                } else if next == b'H' {
+                    key.push('0');
+                } else if !(next == b'C' && after_next == b'H') {
+                    key.push('T');
+                }
+            }
+            b'V' => key.push('F'),
+            b'W' | b'Y' => {
+                if is_vowel(next) {
+                    key.push(ch as char);
+                }
+            }
+            b'X' => key.push_str("KS"),
+            b'Z' => key.push('S'),
+            _ => key.push(ch as char),
+        }
+    }
+
+    if key.is_empty() {
+        None
+    } else {
+        Some(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(word: &str) -> String {
+        phonetic_key(word).unwrap()
+    }
+
+    #[test]
+    fn homophones_share_keys() {
+        assert_eq!(key("four"), key("for"));
+        assert_eq!(key("site"), key("sight"));
+        assert_eq!(key("knight"), key("night"));
+        assert_eq!(key("write"), key("right"));
+        assert_eq!(key("ate"), key("eight"));
+    }
+
+    #[test]
+    fn different_words_have_different_keys() {
+        assert_ne!(key("inbox"), key("outbox"));
+        assert_ne!(key("sign"), key("site"));
+        assert_eq!(key("thumb"), "0M");
+        assert_eq!(key("phone"), "FN");
+    }
+
+    #[test]
+    fn words_without_letters_have_no_key() {
+        assert_eq!(phonetic_key(""), None);
+        assert_eq!(phonetic_key("42"), None);
+    }
+}
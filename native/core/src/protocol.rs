@@ -0,0 +1,567 @@
+//! The messages exchanged with the browser extension and the Talon script. Both are
+//! JSON, with enums externally tagged and ids in camel case.
+use crate::config::Confidence;
+use crate::index::{Document, DocumentId, Layout, Score, ScoreResult};
+use crate::spoken::SpokenForms;
+use rustc_hash::FxHashSet;
+
+/// The browser's id for a tab, which is unique for as long as the browser runs.
+pub type TabId = u64;
+
+/// Messages the browser extension sends over native messaging.
+#[derive(Serialize, Deserialize)]
+pub enum MessageFromBrowser {
+    /// The tab is now the one in front, so Talon should be given its words.
+    FocusTab {
+        /// The focused tab.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+    },
+    /// The tab is showing a new page (or the host restarted), so its index should start
+    /// over from the last snapshot of `url`, if any.
+    LoadPage {
+        /// The tab showing the page.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+        /// The page's URL, which snapshots and clicks are kept by.
+        url: String,
+    },
+    /// Links which were added, changed or removed in the tab.
+    UpdateIndex {
+        /// The tab the links are in.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+        /// New or changed links.
+        updated: Vec<Document>,
+        /// Links which are gone.
+        removed: Vec<DocumentId>,
+        /// When set, `updated` is every document on the page, and any others in the
+        /// index (say, from a snapshot) are stale.
+        #[serde(default)]
+        complete: bool,
+        /// New layouts of documents in the index, or in `updated`. Scrolling only changes
+        /// these, so they can be sent without any documents.
+        #[serde(default)]
+        layouts: Vec<(DocumentId, Layout)>,
+    },
+    /// The tab was closed, so its index can be dropped.
+    CloseTab {
+        /// The closed tab.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+    },
+    /// The browser clicked document `id`, from the results of the last query.
+    Clicked {
+        /// The tab the link is in.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+        /// The link which was clicked.
+        id: DocumentId,
+    },
+}
+
+/// What the browser does with the link a query found.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+    /// Click the link.
+    #[default]
+    Click,
+    /// Open the link in a new tab, without switching to it.
+    OpenInBackgroundTab,
+    /// Move the keyboard focus to the link.
+    Focus,
+    /// Move the mouse over the link.
+    Hover,
+    /// Click the link with the middle button.
+    MiddleClick,
+    /// Copy the link's address to the clipboard.
+    CopyHref,
+    /// Download what the link points to.
+    Download,
+}
+
+/// Messages the Talon script writes. Queries say what to do with the link they find,
+/// which is to click it unless they say otherwise.
+#[derive(Serialize, Deserialize)]
+pub enum MessageFromTalon {
+    /// Find the link in the tab which best matches what was said.
+    Query {
+        /// The focused tab.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+        /// What was said.
+        query: String,
+        /// What to do with the link.
+        #[serde(default)]
+        action: Action,
+    },
+    /// Several hearings of one query, each with how likely the recognizer thinks it is.
+    QueryAlternatives {
+        /// The focused tab.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+        /// Each hearing, with its likelihood.
+        alternatives: Vec<(String, f32)>,
+        /// What to do with the link.
+        #[serde(default)]
+        action: Action,
+    },
+    /// Search every open tab, not just the focused one `tab_id`.
+    QueryAllTabs {
+        /// The focused tab.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+        /// What was said.
+        query: String,
+        /// What to do with the link.
+        #[serde(default)]
+        action: Action,
+    },
+    /// Forget which links were clicked on the site in the tab.
+    ForgetClicks {
+        /// The focused tab.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+    },
+    /// Forget which links were clicked on every site.
+    ForgetAllClicks {},
+    /// Label the links on screen in the tab with words to say.
+    ShowHints {
+        /// The focused tab.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+    },
+    /// Click the link which was given `label` by the last `ShowHints`.
+    QueryLabel {
+        /// The focused tab.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+        /// The label which was said.
+        label: String,
+        /// What to do with the link.
+        #[serde(default)]
+        action: Action,
+    },
+    /// Talon missed a delta, and needs the full word set again.
+    Resync {
+        /// The focused tab.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+    },
+}
+
+/// Messages for the Talon script, which keep its list of words in sync with the
+/// focused tab. Words are sent as a map from each way of saying them to how they are
+/// written, and removed by how they are written.
+#[derive(Serialize, Deserialize)]
+pub enum MessageToTalon {
+    /// Every word in the tab, replacing the ones Talon has.
+    UpdateTalonRequest {
+        /// The tab the words are from.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+        /// The words, by how they are said.
+        words: SpokenForms,
+    },
+    /// The words which were added to or removed from the tab since the last update.
+    UpdateTalonDelta {
+        /// The tab the words are from.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+        /// New words, by how they are said.
+        added: SpokenForms,
+        /// Words which are gone, as they are written.
+        removed: FxHashSet<String>,
+    },
+    /// The hint labels shown in the tab, which can be said after "surf hint".
+    UpdateHints {
+        /// The tab the hints are shown in.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+        /// The labels.
+        labels: Vec<String>,
+    },
+}
+
+/// Every message to Talon is numbered, so that Talon can tell when it missed a delta
+/// (the input file is overwritten by each message, and a socket client may connect
+/// after the full word set was sent) and ask for a resync.
+#[derive(Serialize)]
+pub struct SequencedMessageToTalon<'a> {
+    /// One more than the number of the last message sent.
+    pub sequence: u64,
+    /// The message.
+    #[serde(flatten)]
+    pub message: &'a MessageToTalon,
+}
+
+/// A message from the Talon script in the output file. The script numbers its messages
+/// in increasing order, so that one which is read twice (or an old one read again) can
+/// be ignored. Older scripts don't number them at all.
+#[derive(Deserialize)]
+pub struct SequencedMessageFromTalon {
+    /// The message's number, if the script gave it one.
+    #[serde(default)]
+    pub sequence: Option<u64>,
+    /// The message.
+    #[serde(flatten)]
+    pub message: MessageFromTalon,
+}
+
+/// A message from a Talon client on the socket, numbered by the client so that it can
+/// be acknowledged.
+#[derive(Deserialize)]
+pub struct TalonRequest {
+    /// The client's number for the request.
+    pub id: u64,
+    /// The message.
+    #[serde(flatten)]
+    pub message: MessageFromTalon,
+}
+
+/// Just the id of a request, for acknowledging one which couldn't be understood.
+#[derive(Deserialize)]
+pub struct TalonRequestId {
+    /// The client's number for the request.
+    pub id: u64,
+}
+
+/// Sent to a Talon client on the socket for each of its requests, with the reason the
+/// request was rejected if it was.
+#[derive(Serialize)]
+pub struct TalonAck {
+    /// The id of the request.
+    pub ack: u64,
+    /// Why the request was rejected, if it was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// How sure a query is of its results.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum Classification {
+    /// The best result is far enough ahead to act on.
+    Confident,
+    /// The best few results are too close to tell apart, so the browser should ask.
+    Ambiguous,
+    /// Nothing scored above the floor.
+    NoMatch,
+}
+
+/// The results of a query, for the browser to act on. Results are best first, and when
+/// ambiguous, only those too close to the best to tell apart from it. Hints are labels
+/// for the browser to show over the links.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum MessageToBrowser {
+    /// The results of a query over one tab.
+    Results {
+        /// The tab which was searched.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+        /// How sure the query is of `best`.
+        classification: Classification,
+        /// The links found.
+        best: Vec<DocumentId>,
+        /// The score of each link in `best`.
+        scores: Vec<Score>,
+        /// What to do with the link.
+        action: Action,
+    },
+    /// The results of a query over every tab, so the browser may need to switch tabs.
+    CrossTabResults {
+        /// How sure the query is of `best`.
+        classification: Classification,
+        /// The links found, with the tab each is in.
+        best: Vec<(TabId, DocumentId)>,
+        /// The score of each link in `best`.
+        scores: Vec<Score>,
+        /// What to do with the link.
+        action: Action,
+    },
+    /// Labels to show over links in the tab.
+    Hints {
+        /// The tab to show them in.
+        #[serde(rename = "tabId")]
+        tab_id: TabId,
+        /// Each link, with its label.
+        hints: Vec<(DocumentId, String)>,
+    },
+}
+
+// Send top 10 arbitrarily.
+const MAX_RESULTS: usize = 10;
+
+/// Sorts the scores of a query, dropping those below the floor, and decides whether the
+/// best is clear enough to act on.
+fn rank<K: Ord + Copy>(
+    scores: impl IntoIterator<Item = (K, Score)>,
+    confidence: &Confidence,
+) -> (Classification, Vec<(K, Score)>) {
+    let mut best_by_score = scores
+        .into_iter()
+        .filter(|&(_, score)| score >= confidence.floor)
+        .collect::<Vec<(K, Score)>>();
+    // TODO(kvakil): this can be more efficient -- we don't need the whole sort
+    // obviously
+    best_by_score.sort_by(|(key0, score0), (key1, score1)| {
+        score1
+            .partial_cmp(score0)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(key0.cmp(key1))
+    });
+    best_by_score.truncate(MAX_RESULTS);
+    let classification = match best_by_score.as_slice() {
+        [] => Classification::NoMatch,
+        [(_, best), (_, second), ..] if best - second < confidence.margin * best => {
+            let threshold = best - confidence.margin * best;
+            best_by_score.retain(|&(_, score)| score > threshold);
+            Classification::Ambiguous
+        }
+        _ => Classification::Confident,
+    };
+    (classification, best_by_score)
+}
+
+/// Ranks the scores of a query over one tab.
+pub fn rank_results(
+    tab_id: TabId,
+    scores: ScoreResult,
+    action: Action,
+    confidence: &Confidence,
+) -> MessageToBrowser {
+    let (classification, best_by_score) = rank(scores, confidence);
+    MessageToBrowser::Results {
+        tab_id,
+        classification,
+        best: best_by_score.iter().map(|&(id, _)| id).collect(),
+        scores: best_by_score.iter().map(|&(_, score)| score).collect(),
+        action,
+    }
+}
+
+/// Ranks the scores of a query over every tab, which should already be weighted by
+/// which tab they came from.
+pub fn rank_cross_tab_results(
+    scores: Vec<((TabId, DocumentId), Score)>,
+    action: Action,
+    confidence: &Confidence,
+) -> MessageToBrowser {
+    let (classification, best_by_score) = rank(scores, confidence);
+    MessageToBrowser::CrossTabResults {
+        classification,
+        best: best_by_score.iter().map(|&(id, _)| id).collect(),
+        scores: best_by_score.iter().map(|&(_, score)| score).collect(),
+        action,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::index::{DocumentContent, TfidfIndex};
+
+    #[test]
+    fn browser_documents_are_text_or_fields() {
+        let message: MessageFromBrowser = serde_json::from_str(
+            r#"{"UpdateIndex": {"tabId": 1, "removed": [], "updated": [
+                [0, "Sign in"],
+                [1, {"text": "×", "ariaLabel": "Close"}]
+            ], "layouts": [
+                [1, {"visible": true, "top": 8, "left": 900, "width": 24, "height": 24, "fontSize": 20}]
+            ]}}"#,
+        )
+        .unwrap();
+        match message {
+            MessageFromBrowser::UpdateIndex {
+                updated, layouts, ..
+            } => {
+                assert_eq!(layouts.len(), 1);
+                assert!(layouts[0].1.visible && layouts[0].1.font_size == 20.0);
+                assert!(
+                    matches!(&updated[0], (0, DocumentContent::Text(text)) if text == "Sign in")
+                );
+                assert!(
+                    matches!(&updated[1], (1, DocumentContent::Fields(fields)) if fields["ariaLabel"] == "Close")
+                );
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn talon_messages_are_sequenced() {
+        let message = MessageToTalon::UpdateTalonDelta {
+            tab_id: 3,
+            added: vec![("pee dee eff".to_string(), "pdf".to_string())]
+                .into_iter()
+                .collect(),
+            removed: FxHashSet::default(),
+        };
+        assert_eq!(
+            serde_json::to_string(&SequencedMessageToTalon {
+                sequence: 7,
+                message: &message,
+            })
+            .unwrap(),
+            r#"{"sequence":7,"UpdateTalonDelta":{"tabId":3,"added":{"pee dee eff":"pdf"},"removed":[]}}"#
+        );
+        match serde_json::from_str(r#"{"Resync": {"tabId": 3}}"#) {
+            Ok(MessageFromTalon::Resync { tab_id }) => assert_eq!(tab_id, 3),
+            _ => panic!(),
+        }
+        match serde_json::from_str(r#"{"sequence": 9, "Resync": {"tabId": 3}}"#) {
+            Ok(SequencedMessageFromTalon {
+                sequence: Some(9),
+                message: MessageFromTalon::Resync { tab_id: 3 },
+            }) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn words_on_every_link_still_match() {
+        let classify = |texts: &[&str], query: &str| {
+            let mut tfidf_index = TfidfIndex::new(&Config::default());
+            texts
+                .iter()
+                .enumerate()
+                .for_each(|(document_id, text)| tfidf_index.update(document_id, text));
+            match rank_results(
+                3,
+                tfidf_index.score(query),
+                Action::Click,
+                &Confidence::default(),
+            ) {
+                MessageToBrowser::Results {
+                    classification,
+                    best,
+                    ..
+                } => (classification, best),
+                _ => panic!(),
+            }
+        };
+        assert_eq!(
+            classify(&["Inbox"], "inbox"),
+            (Classification::Confident, vec![0])
+        );
+        assert_eq!(
+            classify(&["Reply", "Reply", "Reply"], "reply"),
+            (Classification::Ambiguous, vec![0, 1, 2])
+        );
+    }
+
+    #[test]
+    fn results_are_classified() {
+        let confidence = Confidence {
+            floor: 0.1,
+            margin: 0.2,
+        };
+        let rank = |scores: &[(DocumentId, Score)]| match rank_results(
+            3,
+            scores.iter().cloned().collect(),
+            Action::Click,
+            &confidence,
+        ) {
+            MessageToBrowser::Results {
+                classification,
+                best,
+                scores,
+                ..
+            } => (classification, best, scores),
+            _ => panic!(),
+        };
+
+        assert_eq!(
+            rank(&[(0, 0.2), (1, 1.0), (2, 0.7)]),
+            (
+                Classification::Confident,
+                vec![1, 2, 0],
+                vec![1.0, 0.7, 0.2]
+            )
+        );
+        assert_eq!(
+            rank(&[(0, 0.2), (1, 1.0), (2, 0.9), (3, 0.85)]),
+            (
+                Classification::Ambiguous,
+                vec![1, 2, 3],
+                vec![1.0, 0.9, 0.85]
+            )
+        );
+        assert_eq!(
+            rank(&[(0, 0.5), (1, 0.5)]),
+            (Classification::Ambiguous, vec![0, 1], vec![0.5, 0.5])
+        );
+        assert_eq!(
+            rank(&[(0, 0.05), (1, 0.3)]),
+            (Classification::Confident, vec![1], vec![0.3])
+        );
+        assert_eq!(
+            rank(&[(0, 0.05), (1, 0.0)]),
+            (Classification::NoMatch, vec![], vec![])
+        );
+        assert_eq!(rank(&[(0, 0.05)]).0, Classification::NoMatch);
+    }
+
+    #[test]
+    fn cross_tab_results_name_their_tab() {
+        let message = rank_cross_tab_results(
+            vec![((1, 0), 0.5), ((2, 0), 1.0), ((2, 1), 0.05)],
+            Action::Click,
+            &Confidence::default(),
+        );
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"CrossTabResults":{"classification":"Confident","best":[[2,0],[1,0],[2,1]],"scores":[1.0,0.5,0.05],"action":"Click"}}"#
+        );
+    }
+
+    #[test]
+    fn queries_carry_an_action() {
+        let query = |json| match serde_json::from_str(json).unwrap() {
+            MessageFromTalon::Query { action, .. } => action,
+            _ => panic!(),
+        };
+        assert_eq!(
+            query(r#"{"Query": {"tabId": 1, "query": "inbox"}}"#),
+            Action::Click
+        );
+        assert_eq!(
+            query(r#"{"Query": {"tabId": 1, "query": "inbox", "action": "CopyHref"}}"#),
+            Action::CopyHref
+        );
+        let message = rank_results(
+            1,
+            vec![(4, 1.0)].into_iter().collect(),
+            Action::OpenInBackgroundTab,
+            &Confidence::default(),
+        );
+        assert!(serde_json::to_string(&message)
+            .unwrap()
+            .ends_with(r#""action":"OpenInBackgroundTab"}}"#));
+    }
+
+    #[test]
+    fn socket_requests_are_numbered_and_acknowledged() {
+        let request: TalonRequest =
+            serde_json::from_str(r#"{"id": 5, "Resync": {"tabId": 3}}"#).unwrap();
+        assert_eq!(request.id, 5);
+        match request.message {
+            MessageFromTalon::Resync { tab_id } => assert_eq!(tab_id, 3),
+            _ => panic!(),
+        }
+        assert!(serde_json::from_str::<TalonRequest>(r#"{"id": 6, "Jump": {}}"#).is_err());
+        let id: TalonRequestId = serde_json::from_str(r#"{"id": 6, "Jump": {}}"#).unwrap();
+        assert_eq!(id.id, 6);
+
+        let ack = |error: Option<&str>| {
+            serde_json::to_string(&TalonAck {
+                ack: 6,
+                error: error.map(str::to_string),
+            })
+            .unwrap()
+        };
+        assert_eq!(ack(None), r#"{"ack":6}"#);
+        assert_eq!(ack(Some("no")), r#"{"ack":6,"error":"no"}"#);
+    }
+}
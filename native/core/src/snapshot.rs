@@ -0,0 +1,166 @@
+//! Snapshots of tab indices on disk, so that a tab can be searched as soon as it's
+//! restored or the host restarts, before the browser has sent anything.
+use crate::atomic::write_atomically;
+use crate::config::Config;
+use crate::index::TfidfIndex;
+use crate::protocol::TabId;
+use rustc_hash::FxHasher;
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+// Bumped whenever the layout of TfidfIndex changes, so old snapshots are ignored.
+const SNAPSHOT_VERSION: &str = "v3";
+
+/// How many snapshots are kept by default. Each holds the text of a page, so the cache
+/// shouldn't grow with the browsing history.
+pub const MAX_SNAPSHOTS: usize = 200;
+
+/// How long a snapshot is kept by default after it was last saved.
+pub const MAX_SNAPSHOT_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A directory of index snapshots, keyed by tab and URL.
+#[derive(Clone, Debug)]
+pub struct SnapshotStore {
+    directory: PathBuf,
+}
+
+impl SnapshotStore {
+    /// The snapshots in `directory`, which should already exist.
+    pub fn new(directory: PathBuf) -> Self {
+        SnapshotStore { directory }
+    }
+
+    fn path(&self, tab_id: TabId, url: &str) -> PathBuf {
+        // FxHasher is deterministic, unlike the standard library's default hasher.
+        let mut hasher = FxHasher::default();
+        hasher.write(url.as_bytes());
+        self.directory.join(format!(
+            "{}-{}-{:016x}",
+            SNAPSHOT_VERSION,
+            tab_id,
+            hasher.finish()
+        ))
+    }
+
+    /// Loads the snapshot of `url` in the tab, if there is a usable one.
+    pub fn load(&self, config: &Config, tab_id: TabId, url: &str) -> Option<TfidfIndex> {
+        let snapshot = fs::read(self.path(tab_id, url)).ok()?;
+        TfidfIndex::from_snapshot(config, &snapshot)
+    }
+
+    /// Saves a snapshot of `url` in the tab, replacing any earlier one.
+    pub fn save(&self, tab_id: TabId, url: &str, tfidf_index: &TfidfIndex) -> io::Result<()> {
+        let snapshot = tfidf_index
+            .snapshot()
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        let path = self.path(tab_id, url);
+        write_atomically(&path.with_extension("tmp"), &path, &snapshot)
+    }
+
+    /// Deletes the snapshots saved more than `max_age` ago, and the oldest beyond the
+    /// `max_count` most recently saved, along with any from older versions or left
+    /// half written. Returns how many files were deleted.
+    pub fn prune(&self, max_count: usize, max_age: Duration) -> io::Result<usize> {
+        let now = SystemTime::now();
+        let mut snapshots = Vec::<(SystemTime, PathBuf)>::new();
+        let mut stale = Vec::<PathBuf>::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            let path = entry.path();
+            let current = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    name.starts_with(&format!("{}-", SNAPSHOT_VERSION)) && !name.ends_with(".tmp")
+                });
+            match entry.metadata().and_then(|metadata| metadata.modified()) {
+                Ok(modified)
+                    if current && now.duration_since(modified).unwrap_or_default() <= max_age =>
+                {
+                    snapshots.push((modified, path))
+                }
+                _ => stale.push(path),
+            }
+        }
+        // Newest first.
+        snapshots.sort_by(|(modified0, _), (modified1, _)| modified1.cmp(modified0));
+        stale.extend(snapshots.into_iter().skip(max_count).map(|(_, path)| path));
+        stale.iter().try_for_each(fs::remove_file)?;
+        Ok(stale.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_are_keyed_by_tab_and_url() {
+        let directory = tempfile::tempdir().unwrap();
+        let snapshot_store = SnapshotStore::new(directory.path().to_path_buf());
+        let config = Config::default();
+        let mut tfidf_index = TfidfIndex::new(&config);
+        tfidf_index.update(0, "Inbox");
+        tfidf_index.update(1, "Sent mail");
+        snapshot_store
+            .save(1, "https://mail.example.com/", &tfidf_index)
+            .unwrap();
+
+        let restored = snapshot_store
+            .load(&config, 1, "https://mail.example.com/")
+            .unwrap();
+        assert!(restored.score("inbox")[&0] > 0.0);
+        assert!(snapshot_store
+            .load(&config, 2, "https://mail.example.com/")
+            .is_none());
+        assert!(snapshot_store
+            .load(&config, 1, "https://example.com/")
+            .is_none());
+    }
+
+    #[test]
+    fn old_and_excess_snapshots_are_pruned() {
+        let directory = tempfile::tempdir().unwrap();
+        let snapshot_store = SnapshotStore::new(directory.path().to_path_buf());
+        let tfidf_index = TfidfIndex::new(&Config::default());
+        let day = Duration::from_secs(24 * 60 * 60);
+        let saved = |tab_id: TabId, age: Duration| {
+            snapshot_store
+                .save(tab_id, "https://example.com/", &tfidf_index)
+                .unwrap();
+            fs::File::options()
+                .write(true)
+                .open(snapshot_store.path(tab_id, "https://example.com/"))
+                .unwrap()
+                .set_modified(SystemTime::now() - age)
+                .unwrap();
+        };
+        saved(1, Duration::from_secs(0));
+        saved(2, day);
+        saved(3, 2 * day);
+        saved(4, 30 * day);
+        fs::write(directory.path().join("v1-1-0000000000000000"), b"old").unwrap();
+        fs::write(
+            directory
+                .path()
+                .join(format!("{}-5-0000000000000000.tmp", SNAPSHOT_VERSION)),
+            b"half",
+        )
+        .unwrap();
+
+        assert_eq!(snapshot_store.prune(2, 7 * day).unwrap(), 4);
+        let config = Config::default();
+        let kept: Vec<TabId> = (1..=4)
+            .filter(|&tab_id| {
+                snapshot_store
+                    .load(&config, tab_id, "https://example.com/")
+                    .is_some()
+            })
+            .collect();
+        assert_eq!(kept, vec![1, 2]);
+        assert_eq!(fs::read_dir(directory.path()).unwrap().count(), 2);
+    }
+}
@@ -0,0 +1,192 @@
+//! How to say the words in an index. Talon is sent a map from each spoken form to the
+//! word as written, so that "gh" can be said as "gee aitch" or "github", "k8s" as
+//! "kubernetes", and "c++" as "see plus plus".
+use crate::tokenizer::{is_spoken, tokenize};
+use rustc_hash::FxHashMap;
+
+/// Spoken forms of words, mapped to the words as written.
+pub type SpokenForms = FxHashMap<String, String>;
+
+const LETTER_NAMES: [&str; 26] = [
+    "ay",
+    "bee",
+    "see",
+    "dee",
+    "ee",
+    "eff",
+    "gee",
+    "aitch",
+    "eye",
+    "jay",
+    "kay",
+    "el",
+    "em",
+    "en",
+    "oh",
+    "pee",
+    "cue",
+    "ar",
+    "ess",
+    "tee",
+    "you",
+    "vee",
+    "double you",
+    "ex",
+    "why",
+    "zee",
+];
+
+// Abbreviations which are said as what they stand for.
+const ABBREVIATIONS: [(&str, &str); 12] = [
+    ("2fa", "two factor"),
+    ("gh", "github"),
+    ("i18n", "internationalization"),
+    ("js", "javascript"),
+    ("k8s", "kubernetes"),
+    ("l10n", "localization"),
+    ("mfa", "multi factor"),
+    ("pr", "pull request"),
+    ("prs", "pull requests"),
+    ("ts", "typescript"),
+    ("tz", "time zone"),
+    ("yt", "youtube"),
+];
+
+// Symbols which can end a word, like "c++" and "c#" (see `tokenizer::SYMBOL_SUFFIXES`).
+const SYMBOL_NAMES: [(char, &str); 2] = [('+', "plus"), ('#', "sharp")];
+
+// Words this short without any vowels are read out letter by letter.
+const MAX_SPELLED_LENGTH: usize = 5;
+
+fn letter_name(letter: char) -> Option<&'static str> {
+    match letter {
+        'a'..='z' => Some(LETTER_NAMES[(letter as u8 - b'a') as usize]),
+        _ => None,
+    }
+}
+
+/// Spells a run of lowercase ASCII letters, like "pdf" as "pee dee eff".
+fn spell(letters: &str) -> Option<String> {
+    letters
+        .chars()
+        .map(letter_name)
+        .collect::<Option<Vec<&str>>>()
+        .map(|names| names.join(" "))
+}
+
+/// Whether a run of letters is read out letter by letter, like "gh" or "pdf".
+fn is_spelled(letters: &str) -> bool {
+    letters.len() <= MAX_SPELLED_LENGTH
+        && letters.chars().all(|ch| ch.is_ascii_lowercase())
+        && !letters.contains(['a', 'e', 'i', 'o', 'u', 'y'])
+}
+
+/// How to say a run of letters, as a word if it can be and letter by letter if not.
+fn say_letters(letters: &str) -> Option<String> {
+    if (letters.len() == 1 && letters != "a" && letters != "i") || is_spelled(letters) {
+        spell(letters)
+    } else if is_spoken(letters) {
+        Some(letters.to_string())
+    } else {
+        None
+    }
+}
+
+/// The ways `word` (as indexed) can be said. Words which can't be said at all, like
+/// "€", have none.
+pub fn spoken_forms(word: &str) -> Vec<String> {
+    let mut forms = Vec::new();
+    if let Some(&(_, expansion)) = ABBREVIATIONS
+        .iter()
+        .find(|(abbreviation, _)| *abbreviation == word)
+    {
+        forms.push(expansion.to_string());
+    }
+    let letters = word.trim_end_matches(|ch| SYMBOL_NAMES.iter().any(|&(symbol, _)| symbol == ch));
+    if letters.len() < word.len() {
+        // "c++"
+        let symbols = word[letters.len()..].chars().filter_map(|ch| {
+            SYMBOL_NAMES
+                .iter()
+                .find(|&&(symbol, _)| symbol == ch)
+                .map(|&(_, name)| name)
+        });
+        if let Some(letters) = say_letters(letters) {
+            forms.push(
+                std::iter::once(letters.as_str())
+                    .chain(symbols)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            );
+        }
+    } else if word.chars().any(|ch| ch.is_ascii_digit()) {
+        // "mp3" is indexed along with "mp" and "three", which say it.
+        let parts = tokenize(word)
+            .into_iter()
+            .skip(1)
+            .map(|part| say_letters(&part))
+            .collect::<Option<Vec<String>>>();
+        if let Some(parts) = parts.filter(|parts| !parts.is_empty()) {
+            forms.push(parts.join(" "));
+        }
+    } else if let Some(said) = say_letters(word) {
+        forms.push(said);
+    }
+    forms.dedup();
+    forms
+}
+
+/// Maps the spoken forms of `words` to them. When two words can be said the same way,
+/// a word said as written wins.
+pub fn spoken_form_map<'a>(words: impl IntoIterator<Item = &'a str>) -> SpokenForms {
+    let mut map = SpokenForms::default();
+    for word in words {
+        for form in spoken_forms(word) {
+            match map.get(&form) {
+                Some(written) if *written == form => {}
+                _ => {
+                    map.insert(form, word.to_string());
+                }
+            }
+        }
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_are_said_as_written() {
+        assert_eq!(spoken_forms("inbox"), vec!["inbox"]);
+        assert_eq!(spoken_forms("a"), vec!["a"]);
+        assert_eq!(spoken_forms("café"), vec!["café"]);
+        assert!(spoken_forms("€").is_empty());
+    }
+
+    #[test]
+    fn abbreviations_are_spelled_or_expanded() {
+        assert_eq!(spoken_forms("pdf"), vec!["pee dee eff"]);
+        assert_eq!(spoken_forms("gh"), vec!["github", "gee aitch"]);
+        assert_eq!(spoken_forms("k"), vec!["kay"]);
+        assert_eq!(spoken_forms("k8s"), vec!["kubernetes", "kay eight ess"]);
+        assert_eq!(spoken_forms("2fa"), vec!["two factor", "two fa"]);
+        assert_eq!(spoken_forms("mp3"), vec!["em pee three"]);
+    }
+
+    #[test]
+    fn symbols_are_named() {
+        assert_eq!(spoken_forms("c++"), vec!["see plus plus"]);
+        assert_eq!(spoken_forms("f#"), vec!["eff sharp"]);
+    }
+
+    #[test]
+    fn words_said_as_written_win() {
+        let map = spoken_form_map(vec!["github", "gh"]);
+        assert_eq!(map["github"], "github");
+        assert_eq!(map["gee aitch"], "gh");
+        let map = spoken_form_map(vec!["gh", "github"]);
+        assert_eq!(map["github"], "github");
+    }
+}
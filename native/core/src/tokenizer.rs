@@ -0,0 +1,379 @@
+//! Turns document text into the words we index. Everything produced here should be
+//! something a user can say, since the words are also sent to Talon as vocabulary.
+
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+const ONES: [&str; 20] = [
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+
+const TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+// Numbers with more digits than this are read out one digit at a time (phone numbers,
+// order IDs, ...), since nobody says "four million two hundred thousand ...".
+const MAX_CARDINAL_DIGITS: usize = 6;
+
+// Digit runs inside a mixed token longer than this are read one digit at a time, so
+// "h264" is "h two six four" but "mp3" is "mp three" and "win10" is "win ten".
+const MAX_MIXED_CARDINAL_DIGITS: usize = 2;
+
+fn push_digits(digits: &str, words: &mut Vec<String>) {
+    digits
+        .bytes()
+        .for_each(|digit| words.push(ONES[(digit - b'0') as usize].to_string()));
+}
+
+fn push_below_hundred(n: usize, words: &mut Vec<String>) {
+    if n < 20 {
+        words.push(ONES[n].to_string());
+    } else {
+        let (tens, ones) = (n / 10, n % 10);
+        words.push(TENS[tens].to_string());
+        if ones != 0 {
+            words.push(ONES[ones].to_string());
+        }
+    }
+}
+
+fn push_below_thousand(n: usize, words: &mut Vec<String>) {
+    let (hundreds, rest) = (n / 100, n % 100);
+    if hundreds > 0 {
+        words.push(ONES[hundreds].to_string());
+        words.push("hundred".to_string());
+        if rest != 0 {
+            push_below_hundred(rest, words);
+        }
+    } else {
+        push_below_hundred(n, words);
+    }
+}
+
+fn push_cardinal(n: usize, words: &mut Vec<String>) {
+    let (thousands, rest) = (n / 1000, n % 1000);
+    if thousands > 0 {
+        push_below_thousand(thousands, words);
+        words.push("thousand".to_string());
+        if rest != 0 {
+            push_below_thousand(rest, words);
+        }
+    } else {
+        push_below_thousand(n, words);
+    }
+}
+
+/// Pushes the spoken form of a run of ASCII digits onto `words`. Four digit numbers
+/// are read like years ("2020" is "twenty twenty"), since that's what they usually are.
+pub fn push_number(digits: &str, words: &mut Vec<String>) {
+    if digits.len() > MAX_CARDINAL_DIGITS || (digits.len() > 1 && digits.starts_with('0')) {
+        push_digits(digits, words);
+        return;
+    }
+    let n: usize = digits.parse().unwrap();
+    let (high, low) = (n / 100, n % 100);
+    if digits.len() != 4 || (high % 10 == 0 && low < 10) {
+        // "2005" is "two thousand five", not "twenty oh five".
+        push_cardinal(n, words);
+    } else if low == 0 {
+        push_below_hundred(high, words);
+        words.push("hundred".to_string());
+    } else if low < 10 {
+        push_below_hundred(high, words);
+        words.push("oh".to_string());
+        words.push(ONES[low].to_string());
+    } else {
+        push_below_hundred(high, words);
+        push_below_hundred(low, words);
+    }
+}
+
+/// Splits `token` into runs of digits and runs of anything else, dropping punctuation.
+fn split_digit_runs(token: &str) -> Vec<&str> {
+    let mut runs = Vec::new();
+    token
+        .split(|ch: char| !ch.is_alphanumeric())
+        .filter(|piece| !piece.is_empty())
+        .for_each(|piece| {
+            let mut start = 0;
+            let mut previous_is_digit = None;
+            for (i, ch) in piece.char_indices() {
+                let is_digit = ch.is_ascii_digit();
+                if previous_is_digit.is_some_and(|previous| previous != is_digit) {
+                    runs.push(&piece[start..i]);
+                    start = i;
+                }
+                previous_is_digit = Some(is_digit);
+            }
+            runs.push(&piece[start..]);
+        });
+    runs
+}
+
+fn push_token(token: &str, words: &mut Vec<String>) {
+    if !token.chars().any(|ch| ch.is_ascii_digit()) {
+        words.push(token.to_string());
+        return;
+    }
+    let number: String = token.chars().filter(|&ch| ch != ',').collect();
+    if number.chars().all(|ch| ch.is_ascii_digit()) {
+        // "1,000"
+        push_number(&number, words);
+        return;
+    }
+    // Keep the written form of tokens like "mp3" so they can still be typed, and
+    // index each run of letters and digits so they can be said.
+    words.push(token.to_string());
+    split_digit_runs(token).into_iter().for_each(|run| {
+        if !run.starts_with(|ch: char| ch.is_ascii_digit()) {
+            words.push(run.to_string());
+        } else if run.len() > MAX_MIXED_CARDINAL_DIGITS {
+            push_digits(run, words);
+        } else {
+            push_number(run, words);
+        }
+    });
+}
+
+/// Lowercases `word` and strips any accents from Latin and Greek letters, so "Café"
+/// is "cafe" and "Straße" is "strasse". Other scripts keep their marks, since they are
+/// usually separate letters there ("й" is not "и").
+pub fn fold(word: &str) -> String {
+    word.nfc()
+        .map(|ch| match std::iter::once(ch).nfd().next() {
+            Some(base) if base.is_ascii() || ('\u{370}'..='\u{3ff}').contains(&base) => base,
+            _ => ch,
+        })
+        .flat_map(char::to_lowercase)
+        .collect::<String>()
+        .replace('ß', "ss")
+}
+
+/// Whether `ch` belongs to a script which is written without spaces between words.
+fn is_unspaced(ch: char) -> bool {
+    matches!(ch,
+        '\u{0e00}'..='\u{0eff}' // Thai, Lao
+        | '\u{1000}'..='\u{109f}' // Myanmar
+        | '\u{1780}'..='\u{17ff}' // Khmer
+        | '\u{3040}'..='\u{30ff}' // Hiragana, Katakana
+        | '\u{31f0}'..='\u{31ff}' // Katakana extensions
+        | '\u{3400}'..='\u{4dbf}' // CJK extension A
+        | '\u{4e00}'..='\u{9fff}' // CJK unified ideographs
+        | '\u{f900}'..='\u{faff}' // CJK compatibility ideographs
+        | '\u{20000}'..='\u{2ffff}' // CJK extensions B onwards
+    )
+}
+
+/// Indexes text in scripts without spaces as overlapping character bigrams, since we
+/// don't know where the words are. A single character is indexed on its own.
+fn push_bigrams(run: &[char], words: &mut Vec<String>) {
+    if run.len() == 1 {
+        words.push(run[0].to_string());
+    } else {
+        run.windows(2)
+            .for_each(|bigram| words.push(bigram.iter().collect()));
+    }
+}
+
+/// Symbols which are part of the word they follow, like in "C++" and "C#". Words
+/// ending in these are indexed with and without them.
+pub const SYMBOL_SUFFIXES: [&str; 2] = ["++", "#"];
+
+/// The symbols at the start of `rest` which belong to the word before it.
+fn symbol_suffix(rest: &str) -> Option<&'static str> {
+    SYMBOL_SUFFIXES.iter().cloned().find(|suffix| {
+        rest.strip_prefix(suffix)
+            .is_some_and(|after| !after.starts_with(char::is_alphanumeric))
+    })
+}
+
+fn is_compound_separator(ch: char) -> bool {
+    ch == '_' || ch == '.'
+}
+
+/// Splits `part` at camelCase boundaries, so "GitHub" is "Git" and "Hub", and
+/// "XMLHttpRequest" is "XML", "Http" and "Request".
+fn split_camel_case(part: &str) -> Vec<&str> {
+    let chars: Vec<(usize, char)> = part.char_indices().collect();
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    for i in 1..chars.len() {
+        let (offset, ch) = chars[i];
+        let previous = chars[i - 1].1;
+        let lower_to_upper = !previous.is_uppercase() && ch.is_uppercase();
+        let acronym_end = previous.is_uppercase()
+            && ch.is_uppercase()
+            && chars
+                .get(i + 1)
+                .is_some_and(|(_, next)| next.is_lowercase());
+        if lower_to_upper || acronym_end {
+            pieces.push(&part[start..offset]);
+            start = offset;
+        }
+    }
+    pieces.push(&part[start..]);
+    pieces
+}
+
+fn push_compound(compound: &str, words: &mut Vec<String>) {
+    let parts: Vec<&str> = compound
+        .split(is_compound_separator)
+        .filter(|part| !part.is_empty())
+        .flat_map(split_camel_case)
+        .collect();
+    if parts.len() == 1 {
+        push_token(&fold(compound), words);
+    } else {
+        // Index the whole compound next to its parts, so both "github" and "git hub"
+        // match "GitHub".
+        words.push(fold(compound));
+        parts.iter().for_each(|part| push_token(&fold(part), words));
+    }
+}
+
+/// Splits `text` into folded words, spelling out any numbers and splitting compound
+/// words like "GitHub", "snake_case" and "readme.md".
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut unspaced_run = Vec::<char>::new();
+    let mut unspaced_run_end = 0;
+    for (start, word) in text.unicode_word_indices() {
+        if word.chars().all(is_unspaced) {
+            if start != unspaced_run_end && !unspaced_run.is_empty() {
+                push_bigrams(&unspaced_run, &mut words);
+                unspaced_run.clear();
+            }
+            unspaced_run.extend(word.chars());
+            unspaced_run_end = start + word.len();
+            continue;
+        }
+        if !unspaced_run.is_empty() {
+            push_bigrams(&unspaced_run, &mut words);
+            unspaced_run.clear();
+        }
+        let compound = word.trim_matches(is_compound_separator);
+        if !compound.is_empty() {
+            push_compound(compound, &mut words);
+            if let Some(suffix) = symbol_suffix(&text[start + word.len()..]) {
+                words.push(fold(word) + suffix);
+            }
+        }
+    }
+    if !unspaced_run.is_empty() {
+        push_bigrams(&unspaced_run, &mut words);
+    }
+    words
+}
+
+/// Whether `word` is only letters (and apostrophes, as in "don't"), which is what
+/// `spoken::spoken_forms` needs to say a run of letters as a word rather than dropping it.
+pub fn is_spoken(word: &str) -> bool {
+    word.chars().all(|ch| ch.is_alphabetic() || ch == '\'')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(digits: &str) -> String {
+        let mut words = Vec::new();
+        push_number(digits, &mut words);
+        words.join(" ")
+    }
+
+    #[test]
+    fn spells_numbers() {
+        assert_eq!(number("0"), "zero");
+        assert_eq!(number("2"), "two");
+        assert_eq!(number("10"), "ten");
+        assert_eq!(number("42"), "forty two");
+        assert_eq!(number("100"), "one hundred");
+        assert_eq!(number("115"), "one hundred fifteen");
+        assert_eq!(number("12345"), "twelve thousand three hundred forty five");
+        assert_eq!(number("007"), "zero zero seven");
+        assert_eq!(
+            number("5551234567"),
+            "five five five one two three four five six seven"
+        );
+    }
+
+    #[test]
+    fn spells_years() {
+        assert_eq!(number("2020"), "twenty twenty");
+        assert_eq!(number("1999"), "nineteen ninety nine");
+        assert_eq!(number("1905"), "nineteen oh five");
+        assert_eq!(number("1900"), "nineteen hundred");
+        assert_eq!(number("2005"), "two thousand five");
+    }
+
+    #[test]
+    fn tokenizes_mixed_tokens() {
+        assert_eq!(tokenize("Page 2"), vec!["page", "two"]);
+        assert_eq!(tokenize("Top 10!"), vec!["top", "ten"]);
+        assert_eq!(tokenize("v3"), vec!["v3", "v", "three"]);
+        assert_eq!(tokenize("MP3"), vec!["mp3", "mp", "three"]);
+        assert_eq!(tokenize("h264"), vec!["h264", "h", "two", "six", "four"]);
+        assert_eq!(tokenize("k8s"), vec!["k8s", "k", "eight", "s"]);
+    }
+
+    #[test]
+    fn tokenizes_symbol_suffixes() {
+        assert_eq!(tokenize("C++ and C#"), vec!["c", "c++", "and", "c", "c#"]);
+        assert_eq!(tokenize("issue #4"), vec!["issue", "four"]);
+        assert_eq!(tokenize("a#b"), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn tokenizes_compound_words() {
+        assert_eq!(tokenize("GitHub"), vec!["github", "git", "hub"]);
+        assert_eq!(
+            tokenize("Learn JavaScript."),
+            vec!["learn", "javascript", "java", "script"]
+        );
+        assert_eq!(
+            tokenize("XMLHttpRequest"),
+            vec!["xmlhttprequest", "xml", "http", "request"]
+        );
+        assert_eq!(tokenize("snake_case"), vec!["snake_case", "snake", "case"]);
+        assert_eq!(tokenize("README.md"), vec!["readme.md", "readme", "md"]);
+        assert_eq!(tokenize("...and more_"), vec!["and", "more"]);
+    }
+
+    #[test]
+    fn tokenizes_unicode() {
+        assert_eq!(tokenize("Café"), vec!["cafe"]);
+        assert_eq!(tokenize("Große Straße"), vec!["grosse", "strasse"]);
+        assert_eq!(tokenize("Über uns"), vec!["uber", "uns"]);
+        assert_eq!(tokenize("Войти в систему"), vec!["войти", "в", "систему"]);
+        assert_eq!(tokenize("l'été — d'accord"), vec!["l'ete", "d'accord"]);
+        assert_eq!(tokenize("1,000 points"), vec!["one", "thousand", "points"]);
+        assert_eq!(tokenize("!!! ... ---"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn tokenizes_unspaced_scripts_as_bigrams() {
+        assert_eq!(tokenize("設定を開く"), vec!["設定", "定を", "を開", "開く"]);
+        assert_eq!(tokenize("東京 大阪"), vec!["東京", "大阪"]);
+        assert_eq!(tokenize("新 GitHub"), vec!["新", "github", "git", "hub"]);
+    }
+}
@@ -1,14 +1,17 @@
-// TODO(kvakil): this is all one big file. split it.
+//! The native messaging host: a thin layer of threads which shuttles messages between
+//! the browser, Talon and one voicesurf_core::TfidfIndex per tab.
 use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use std::convert::TryInto;
 use std::fs;
-use std::fs::File;
 use std::io;
-use std::io::{Read, Seek, Write};
-use std::iter::FromIterator;
-use std::path::Path;
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 extern crate byteorder;
 use byteorder::NativeEndian;
 use byteorder::ReadBytesExt;
@@ -16,175 +19,76 @@ extern crate serde;
 extern crate serde_json;
 extern crate xdg;
 
-#[macro_use]
-extern crate serde_derive;
-
-use itertools::Itertools;
 use rustc_hash::{FxHashMap, FxHashSet};
+use std::collections::hash_map::Entry;
+use voicesurf_core::aliases::Aliases;
+use voicesurf_core::atomic::write_atomically;
+use voicesurf_core::config::{self, Config};
+use voicesurf_core::frame::{read_frame, write_frame};
+use voicesurf_core::hints::Hints;
+use voicesurf_core::history::{domain, ClickHistory};
+use voicesurf_core::index::Document;
+use voicesurf_core::protocol::{
+    rank_cross_tab_results, rank_results, Action, MessageFromBrowser, MessageFromTalon,
+    MessageToBrowser, MessageToTalon, SequencedMessageFromTalon, SequencedMessageToTalon, TabId,
+    TalonAck, TalonRequest, TalonRequestId,
+};
+use voicesurf_core::snapshot::{SnapshotStore, MAX_SNAPSHOTS, MAX_SNAPSHOT_AGE};
+use voicesurf_core::spoken::{spoken_form_map, SpokenForms};
+use voicesurf_core::{DocumentId, Layout, Score, ScoreResult, TfidfIndex};
 
 // TODO(kvakil): check sync version between this and Talon script for IPC?
 const VERSION: &str = "v0";
 
-type DocumentId = usize;
-type Document = (DocumentId, String);
-type Word = String;
-type Score = f32;
-type ScoreResult = FxHashMap<DocumentId, Score>;
-
-#[derive(Debug)]
-struct WordIndex {
-    frequency_by_document: FxHashMap<DocumentId, f32>,
-}
-
-#[derive(Debug)]
-struct TfidfIndex {
-    number_of_documents: usize,
-    document_bags: FxHashMap<DocumentId, FxHashSet<String>>,
-    word_indices: FxHashMap<Word, WordIndex>,
-}
+const CLICK_HISTORY_FILENAME: &str = "clicks.json";
+const ALIASES_FILENAME: &str = "aliases.json";
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
 
 // TODO(kvakil): we should remove all uses us this function.
-fn ignore<T>(_: T) -> () {}
-
-// TODO(kvakil): make this impl TfidfIndex.
-fn score(tfidf_index: &TfidfIndex, query: String) -> ScoreResult {
-    let mut scores = FxHashMap::<DocumentId, Score>::default();
-    // TODO(kvakil): do we want .unique()?
-    query.split_whitespace().unique().for_each(|word| {
-        let word_index = tfidf_index.word_indices.get(word);
-        match word_index {
-            None => return,
-            Some(word_index) => {
-                // TODO(kvakil): better weighing
-                let idf = ((1 + tfidf_index.number_of_documents) as f32
-                    / ((1 + word_index.frequency_by_document.len()) as f32))
-                    .ln();
-                word_index
-                    .frequency_by_document
-                    .iter()
-                    .for_each(|(&document_id, &tf)| {
-                        let score = scores.entry(document_id).or_insert(0.0);
-                        *score += tf * idf;
-                    });
-            }
-        }
-    });
-    return scores;
-}
-
-// TODO(kvakil): make this impl TfidfIndex.
-fn update_index(tfidf_index: &mut TfidfIndex, document_id: DocumentId, document_content: &String) {
-    // TODO(kvakil): better token stream. We can split inside a word, and translate numbers.
-    let words: Vec<String> = document_content
-        .split(|ch: char| !ch.is_ascii_alphabetic())
-        .map(|word| word.to_ascii_lowercase())
-        .collect();
-    let inverse_document_length: f32 = (words.len() as f32).recip();
-    remove_from_index(tfidf_index, document_id);
-    let bag_of_words = tfidf_index
-        .document_bags
-        .entry(document_id)
-        .or_insert_with(|| FxHashSet::<String>::default());
-    for word in words {
-        let word_index = tfidf_index
-            .word_indices
-            .entry(word.to_string())
-            .or_insert_with(|| WordIndex {
-                frequency_by_document: FxHashMap::<DocumentId, f32>::default(),
-            });
-        let frequency_in_document = (*word_index)
-            .frequency_by_document
-            .entry(document_id)
-            .or_insert(0.0);
-        *frequency_in_document += inverse_document_length;
-        bag_of_words.insert(word.to_string());
-    }
-    tfidf_index.number_of_documents += 1
-}
-
-// TODO(kvakil): make this impl TfidfIndex.
-fn remove_from_index(tfidf_index: &mut TfidfIndex, document_id: DocumentId) -> Option<()> {
-    // TODO(kvakil): we slowly leak memory, because if a word no longer has any documents
-    // associated with it, it still has an entry in the word_indices map. OTOH, it will
-    // probably be added in update_index. Perhaps a background task to compress "unused"
-    // entries? (Or likely, this is all too complicated, and we can live with the leak.
-    tfidf_index
-        .document_bags
-        .remove_entry(&document_id)
-        .and_then(|(_, bag)| {
-            tfidf_index.number_of_documents -= 1;
-            bag.iter().for_each(|word| {
-                tfidf_index
-                    .word_indices
-                    .entry(word.to_string())
-                    .and_modify(|word_index| {
-                        word_index.frequency_by_document.remove_entry(&document_id);
-                    });
-            });
-            Some(())
-        })
-}
-
-// TODO(kvakil): make this impl TfidfIndex.
-fn get_words_in_index(tfidf_index: &TfidfIndex) -> FxHashSet<String> {
-    let mut s = FxHashSet::<String>::default();
-    // TODO(kvakil): remove clone
-    for (_, bag) in tfidf_index.document_bags.clone() {
-        for word in bag {
-            s.insert(word);
-        }
-    }
-    s
-}
-
-// TODO(kvakil): make this impl TfidfIndex.
-fn make_index(documents: Vec<Document>) -> TfidfIndex {
-    let mut tfidf_index = TfidfIndex {
-        number_of_documents: 0,
-        word_indices: FxHashMap::<Word, WordIndex>::default(),
-        document_bags: FxHashMap::<DocumentId, FxHashSet<String>>::default(),
-    };
-    // TODO(kvakil): tear this out? I don't think anyone needs the documents argument.
-    documents
-        .iter()
-        .for_each(|(document_id, document)| update_index(&mut tfidf_index, *document_id, document));
-    return tfidf_index;
-}
-
-type TabId = u64;
+fn ignore<T>(_: T) {}
 
+#[allow(clippy::enum_variant_names)]
 enum MessageToParentThread {
     MessageFromBrowser(MessageFromBrowser),
     MessageFromWorkerThread(MessageFromWorkerThread),
-    MessageFromTalonThread(MessageFromTalonThread),
-}
-
-#[derive(Serialize, Deserialize)]
-enum MessageFromBrowser {
-    FocusTab {
-        #[serde(rename = "tabId")]
-        tab_id: TabId,
-    },
-    UpdateIndex {
-        #[serde(rename = "tabId")]
-        tab_id: TabId,
-        updated: Vec<Document>,
-        removed: Vec<DocumentId>,
-    },
-    CloseTab {
-        #[serde(rename = "tabId")]
-        tab_id: TabId,
-    },
+    MessageFromTalonThread(MessageFromTalon),
+    AliasesChanged,
 }
 
 enum MessageToWorkerThread {
     FocusTab {},
+    LoadPage {
+        url: String,
+    },
     UpdateIndex {
         updated: Vec<Document>,
         removed: Vec<DocumentId>,
+        complete: bool,
+        layouts: Vec<(DocumentId, Layout)>,
     },
+    // Queries pass on what to do with their results.
     Query {
         query: String,
+        action: Action,
+    },
+    QueryAlternatives {
+        alternatives: Vec<(String, f32)>,
+        action: Action,
+    },
+    // Part of a query over every tab.
+    Search {
+        search_id: SearchId,
+        query: String,
+    },
+    Clicked {
+        id: DocumentId,
+    },
+    ForgetClicks {},
+    ReloadAliases {},
+    ShowHints {},
+    QueryLabel {
+        label: String,
+        action: Action,
     },
     CloseTab {},
 }
@@ -193,166 +97,744 @@ enum MessageFromWorkerThread {
     Score {
         tab_id: TabId,
         scores: ScoreResult,
+        action: Action,
+    },
+    SearchScore {
+        search_id: SearchId,
+        tab_id: TabId,
+        scores: ScoreResult,
     },
     UpdateTalonRequest {
         tab_id: TabId,
-        words: FxHashSet<String>,
+        words: SpokenForms,
+    },
+    UpdateTalonDelta {
+        tab_id: TabId,
+        added: SpokenForms,
+        removed: FxHashSet<String>,
+    },
+    Hints {
+        tab_id: TabId,
+        hints: Vec<(DocumentId, String)>,
     },
 }
 
 enum MessageToOutputThread {
-    Score { tab_id: TabId, scores: ScoreResult },
-}
-
-#[derive(Serialize, Deserialize)]
-enum MessageFromTalonThread {
-    Query {
-        #[serde(rename = "tabId")]
+    Score {
         tab_id: TabId,
-        query: String,
+        scores: ScoreResult,
+        action: Action,
     },
-}
-
-#[derive(Serialize, Deserialize)]
-enum MessageToTalonThread {
-    UpdateTalonRequest {
-        #[serde(rename = "tabId")]
+    CrossTabScore {
+        scores: Vec<((TabId, DocumentId), Score)>,
+        action: Action,
+    },
+    Hints {
         tab_id: TabId,
-        words: FxHashSet<String>,
+        hints: Vec<(DocumentId, String)>,
     },
 }
 
+type SearchId = u64;
+
+// A query over every tab, waiting on the workers which haven't answered yet.
+struct Search {
+    focused_tab_id: TabId,
+    remaining: usize,
+    scores: Vec<((TabId, DocumentId), Score)>,
+    action: Action,
+    started: Instant,
+}
+
+// A worker which hasn't answered a search by then (say, because it died) is treated
+// as having found nothing, so the search still finishes.
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Finishes the searches which have waited too long, with the scores they have so far.
+fn expire_searches(
+    searches: &mut FxHashMap<SearchId, Search>,
+    output_thread_tx: &mpsc::Sender<MessageToOutputThread>,
+) {
+    let mut expired: Vec<SearchId> = searches
+        .iter()
+        .filter(|(_, search)| search.started.elapsed() >= SEARCH_TIMEOUT)
+        .map(|(&search_id, _)| search_id)
+        .collect();
+    expired.sort_unstable();
+    for search_id in expired {
+        if let Some(search) = searches.remove(&search_id) {
+            eprintln!(
+                "parent: search {} timed out waiting on {} tabs",
+                search_id, search.remaining
+            );
+            ignore(output_thread_tx.send(MessageToOutputThread::CrossTabScore {
+                scores: search.scores,
+                action: search.action,
+            }));
+        }
+    }
+}
+
 struct Thread {
     input: mpsc::Sender<MessageToWorkerThread>,
 }
 
 type WorkerThreads = FxHashMap<TabId, Thread>;
 
+// Snapshots are saved once a tab's index has stopped changing for this long, rather
+// than on every update.
+const SNAPSHOT_DELAY: Duration = Duration::from_secs(2);
+
+// What every worker thread shares.
+#[derive(Clone)]
+struct WorkerContext {
+    config: Config,
+    snapshot_store: Option<SnapshotStore>,
+    click_history: Arc<Mutex<ClickHistory>>,
+    aliases: Arc<RwLock<Aliases>>,
+}
+
+impl WorkerContext {
+    fn new(
+        config: Config,
+        snapshot_store: Option<SnapshotStore>,
+        click_history: ClickHistory,
+        aliases: Aliases,
+    ) -> Self {
+        WorkerContext {
+            config,
+            snapshot_store,
+            click_history: Arc::new(Mutex::new(click_history)),
+            aliases: Arc::new(RwLock::new(aliases)),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn save_click_history(click_history: &ClickHistory) {
+    if let Err(error) = click_history.save() {
+        eprintln!("history: couldn't save: {}", error);
+    }
+}
+
+fn send_vocabulary_changes(
+    tx: &mpsc::Sender<MessageToParentThread>,
+    tab_id: TabId,
+    tfidf_index: &mut TfidfIndex,
+) {
+    let changes = tfidf_index.take_vocabulary_changes();
+    if !changes.is_empty() {
+        ignore(tx.send(MessageToParentThread::MessageFromWorkerThread(
+            MessageFromWorkerThread::UpdateTalonDelta {
+                tab_id,
+                added: spoken_form_map(changes.added.iter().map(String::as_str)),
+                removed: changes.removed,
+            },
+        )));
+    }
+}
+
+fn save_snapshot(
+    snapshot_store: &Option<SnapshotStore>,
+    tab_id: TabId,
+    page: &Option<String>,
+    tfidf_index: &TfidfIndex,
+) {
+    if let (Some(snapshot_store), Some(url)) = (snapshot_store, page) {
+        if let Err(error) = snapshot_store.save(tab_id, url, tfidf_index) {
+            eprintln!("snapshot: couldn't save tab {}: {}", tab_id, error);
+        }
+    }
+}
+
 fn spawn_worker_thread(
     parent_thread_tx: &mpsc::Sender<MessageToParentThread>,
     tab_id: TabId,
+    context: &WorkerContext,
 ) -> Thread {
     let tx = parent_thread_tx.clone();
     let (txp, rxp) = mpsc::channel();
+    let WorkerContext {
+        config,
+        snapshot_store,
+        click_history,
+        aliases,
+    } = context.clone();
     thread::spawn(move || {
-        let mut tfidf_index = make_index(vec![]);
+        let mut tfidf_index = TfidfIndex::new(&config);
+        // The URL of the page in the tab, once the browser has told us.
+        let mut page: Option<String> = None;
+        let mut unsaved = false;
+        // Clicks on our results are credited to the query which found them.
+        let mut last_query: Option<String> = None;
+        // The labels last shown on the page.
+        let mut hints = Hints::default();
+        let boosts = |query: &str, page: &Option<String>| match page {
+            Some(url) => click_history
+                .lock()
+                .unwrap()
+                .boosts(domain(url), query, now()),
+            None => FxHashMap::default(),
+        };
+        let page_aliases = |url: &str| aliases.read().unwrap().for_domain(domain(url));
         loop {
-            match rxp.recv() {
+            match rxp.recv_timeout(SNAPSHOT_DELAY) {
+                Ok(MessageToWorkerThread::LoadPage { url }) => {
+                    if unsaved {
+                        save_snapshot(&snapshot_store, tab_id, &page, &tfidf_index);
+                        unsaved = false;
+                    }
+                    tfidf_index = snapshot_store
+                        .as_ref()
+                        .and_then(|snapshot_store| snapshot_store.load(&config, tab_id, &url))
+                        .unwrap_or_else(|| TfidfIndex::new(&config));
+                    tfidf_index.set_aliases(page_aliases(&url));
+                    page = Some(url);
+                    hints = Hints::default();
+                    tfidf_index.take_vocabulary_changes();
+                    ignore(tx.send(MessageToParentThread::MessageFromWorkerThread(
+                        MessageFromWorkerThread::UpdateTalonRequest {
+                            tab_id,
+                            words: tfidf_index.spoken_words(),
+                        },
+                    )));
+                }
                 Ok(MessageToWorkerThread::FocusTab {}) => {
-                    tx.send(MessageToParentThread::MessageFromWorkerThread(
+                    // Talon is about to get every word, so pending changes are moot.
+                    tfidf_index.take_vocabulary_changes();
+                    ignore(tx.send(MessageToParentThread::MessageFromWorkerThread(
                         MessageFromWorkerThread::UpdateTalonRequest {
                             tab_id,
-                            words: get_words_in_index(&tfidf_index),
+                            words: tfidf_index.spoken_words(),
                         },
-                    ));
+                    )));
                 }
-                Ok(MessageToWorkerThread::UpdateIndex { updated, removed }) => {
+                Ok(MessageToWorkerThread::UpdateIndex {
+                    updated,
+                    removed,
+                    complete,
+                    layouts,
+                }) => {
+                    if complete {
+                        let current: FxHashSet<DocumentId> =
+                            updated.iter().map(|(id, _)| *id).collect();
+                        let stale: Vec<DocumentId> = tfidf_index
+                            .document_ids()
+                            .filter(|id| !current.contains(id))
+                            .collect();
+                        stale.iter().for_each(|id| ignore(tfidf_index.remove(*id)));
+                    }
                     updated
                         .iter()
-                        .for_each(|(id, doc)| update_index(&mut tfidf_index, *id, doc));
+                        .for_each(|(id, doc)| tfidf_index.update_with_content(*id, doc));
 
-                    removed.iter().for_each(|id| {
-                        ignore::<Option<()>>(remove_from_index(&mut tfidf_index, *id))
-                    });
+                    removed
+                        .iter()
+                        .for_each(|id| ignore::<Option<()>>(tfidf_index.remove(*id)));
 
-                    tx.send(MessageToParentThread::MessageFromWorkerThread(
-                        MessageFromWorkerThread::UpdateTalonRequest {
+                    layouts
+                        .into_iter()
+                        .for_each(|(id, layout)| tfidf_index.update_layout(id, layout));
+
+                    // Layouts aren't in snapshots, so an update of only those is cheap.
+                    if complete || !updated.is_empty() || !removed.is_empty() {
+                        send_vocabulary_changes(&tx, tab_id, &mut tfidf_index);
+                        unsaved = true;
+                    }
+                }
+                Ok(MessageToWorkerThread::Search { search_id, query }) => {
+                    ignore(tx.send(MessageToParentThread::MessageFromWorkerThread(
+                        MessageFromWorkerThread::SearchScore {
+                            search_id,
                             tab_id,
-                            words: get_words_in_index(&tfidf_index),
+                            scores: tfidf_index.score_with_boosts(&query, &boosts(&query, &page)),
                         },
-                    ));
+                    )));
+                    last_query = Some(query);
                 }
-                Ok(MessageToWorkerThread::Query { query }) => {
+                Ok(MessageToWorkerThread::Query { query, action }) => {
                     let scores = MessageToParentThread::MessageFromWorkerThread(
                         MessageFromWorkerThread::Score {
                             tab_id,
-                            scores: score(&tfidf_index, query),
+                            scores: tfidf_index.score_with_boosts(&query, &boosts(&query, &page)),
+                            action,
                         },
                     );
-                    tx.send(scores).unwrap()
+                    tx.send(scores).unwrap();
+                    last_query = Some(query);
+                }
+                Ok(MessageToWorkerThread::ShowHints {}) => {
+                    hints = Hints::new(&tfidf_index.visible_documents());
+                    let mut labels: Vec<(DocumentId, String)> = hints
+                        .labels()
+                        .map(|(label, id)| (id, label.to_string()))
+                        .collect();
+                    labels.sort();
+                    ignore(tx.send(MessageToParentThread::MessageFromWorkerThread(
+                        MessageFromWorkerThread::Hints {
+                            tab_id,
+                            hints: labels,
+                        },
+                    )));
+                }
+                Ok(MessageToWorkerThread::QueryLabel { label, action }) => {
+                    // A label can only mean one link, so it's as good a match as can be.
+                    let scores = hints.resolve(&label).map(|id| (id, 1.0)).into_iter();
+                    ignore(tx.send(MessageToParentThread::MessageFromWorkerThread(
+                        MessageFromWorkerThread::Score {
+                            tab_id,
+                            scores: scores.collect(),
+                            action,
+                        },
+                    )));
+                    // Which link a label is for says nothing about what a query means.
+                    last_query = None;
+                }
+                Ok(MessageToWorkerThread::QueryAlternatives {
+                    alternatives,
+                    action,
+                }) => {
+                    let (scores, likeliest) = tfidf_index
+                        .score_alternatives_with_boosts(&alternatives, |query| {
+                            boosts(query, &page)
+                        });
+                    // Clicks are credited to the likeliest alternative which matched.
+                    last_query = likeliest.map(str::to_string);
+                    ignore(tx.send(MessageToParentThread::MessageFromWorkerThread(
+                        MessageFromWorkerThread::Score {
+                            tab_id,
+                            scores,
+                            action,
+                        },
+                    )));
+                }
+                Ok(MessageToWorkerThread::Clicked { id }) => {
+                    if let (Some(query), Some(url), Some(link)) =
+                        (last_query.take(), &page, tfidf_index.link(id))
+                    {
+                        let mut click_history = click_history.lock().unwrap();
+                        click_history.record(domain(url), &query, link.clone(), now());
+                        save_click_history(&click_history);
+                    }
+                }
+                Ok(MessageToWorkerThread::ForgetClicks {}) => {
+                    if let Some(url) = &page {
+                        let mut click_history = click_history.lock().unwrap();
+                        click_history.reset(Some(domain(url)));
+                        save_click_history(&click_history);
+                    }
+                }
+                Ok(MessageToWorkerThread::ReloadAliases {}) => {
+                    if let Some(url) = &page {
+                        tfidf_index.set_aliases(page_aliases(url));
+                        send_vocabulary_changes(&tx, tab_id, &mut tfidf_index);
+                        unsaved = true;
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if unsaved {
+                        save_snapshot(&snapshot_store, tab_id, &page, &tfidf_index);
+                        unsaved = false;
+                    }
+                }
+                Ok(MessageToWorkerThread::CloseTab {})
+                | Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    // Keep the snapshot, in case the tab is restored.
+                    if unsaved {
+                        save_snapshot(&snapshot_store, tab_id, &page, &tfidf_index);
+                    }
+                    break;
                 }
-                Ok(MessageToWorkerThread::CloseTab {}) => break,
-                Err(_) => break,
             }
         }
     });
-    return Thread { input: txp };
+    Thread { input: txp }
 }
 
 fn get_or_spawn_thread<'a>(
     worker_threads: &'a mut WorkerThreads,
     tab_id: TabId,
     parent_thread_tx: &'a mpsc::Sender<MessageToParentThread>,
+    context: &WorkerContext,
 ) -> &'a mut Thread {
-    return worker_threads
+    worker_threads
         .entry(tab_id)
-        .or_insert_with(|| spawn_worker_thread(&parent_thread_tx, tab_id));
+        .or_insert_with(|| spawn_worker_thread(parent_thread_tx, tab_id, context))
 }
 
 fn spawn_parent_thread(
     output_thread_tx: mpsc::Sender<MessageToOutputThread>,
-    talon_thread_tx: mpsc::Sender<MessageToTalonThread>,
+    talon_thread_tx: mpsc::Sender<MessageToTalon>,
+    context: WorkerContext,
 ) -> mpsc::Sender<MessageToParentThread> {
     let (parent_thread_tx, parent_thread_rx) = mpsc::channel::<MessageToParentThread>();
     let parent_thread_tx_for_return = parent_thread_tx.clone();
     let mut threads = FxHashMap::<TabId, Thread>::default();
+    let mut searches = FxHashMap::<SearchId, Search>::default();
+    let mut next_search_id: SearchId = 0;
+    // The tab in front, which is the only one Talon should have the words of. Until the
+    // browser says which it is, every tab's words are passed on.
+    let mut focused_tab_id: Option<TabId> = None;
+    let is_focused = |focused_tab_id: Option<TabId>, tab_id| {
+        focused_tab_id.is_none_or(|focused_tab_id| focused_tab_id == tab_id)
+    };
     thread::spawn(move || loop {
-        let message = parent_thread_rx.recv().unwrap();
+        expire_searches(&mut searches, &output_thread_tx);
+        let message = match parent_thread_rx.recv_timeout(SEARCH_TIMEOUT) {
+            Ok(message) => message,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
         // TODO(kvakil): remove all the ignores here, gracefully[?] handle errors.
         // Parent thread should probably stay around even if a child dies.
         match message {
             MessageToParentThread::MessageFromBrowser(MessageFromBrowser::FocusTab { tab_id }) => {
+                focused_tab_id = Some(tab_id);
                 ignore(
-                    get_or_spawn_thread(&mut threads, tab_id, &parent_thread_tx)
+                    get_or_spawn_thread(&mut threads, tab_id, &parent_thread_tx, &context)
                         .input
                         .send(MessageToWorkerThread::FocusTab {}),
                 )
             }
-            MessageToParentThread::MessageFromTalonThread(MessageFromTalonThread::Query {
+            MessageToParentThread::MessageFromTalonThread(MessageFromTalon::Query {
                 query,
                 tab_id,
+                action,
+            }) => ignore(
+                get_or_spawn_thread(&mut threads, tab_id, &parent_thread_tx, &context)
+                    .input
+                    .send(MessageToWorkerThread::Query { query, action }),
+            ),
+            MessageToParentThread::MessageFromTalonThread(
+                MessageFromTalon::QueryAlternatives {
+                    tab_id,
+                    alternatives,
+                    action,
+                },
+            ) => ignore(
+                get_or_spawn_thread(&mut threads, tab_id, &parent_thread_tx, &context)
+                    .input
+                    .send(MessageToWorkerThread::QueryAlternatives {
+                        alternatives,
+                        action,
+                    }),
+            ),
+            MessageToParentThread::MessageFromBrowser(MessageFromBrowser::Clicked {
+                tab_id,
+                id,
+            }) => ignore(
+                get_or_spawn_thread(&mut threads, tab_id, &parent_thread_tx, &context)
+                    .input
+                    .send(MessageToWorkerThread::Clicked { id }),
+            ),
+            MessageToParentThread::MessageFromTalonThread(MessageFromTalon::ForgetClicks {
+                tab_id,
             }) => ignore(
-                get_or_spawn_thread(&mut threads, tab_id, &parent_thread_tx)
+                get_or_spawn_thread(&mut threads, tab_id, &parent_thread_tx, &context)
                     .input
-                    .send(MessageToWorkerThread::Query { query }),
+                    .send(MessageToWorkerThread::ForgetClicks {}),
             ),
+            MessageToParentThread::AliasesChanged => {
+                context.aliases.write().unwrap().reload();
+                threads.values().for_each(|thread| {
+                    ignore(thread.input.send(MessageToWorkerThread::ReloadAliases {}))
+                });
+            }
+            MessageToParentThread::MessageFromTalonThread(MessageFromTalon::ForgetAllClicks {}) => {
+                let mut click_history = context.click_history.lock().unwrap();
+                click_history.reset(None);
+                save_click_history(&click_history);
+            }
+            MessageToParentThread::MessageFromTalonThread(MessageFromTalon::QueryAllTabs {
+                query,
+                tab_id,
+                action,
+            }) => {
+                let search_id = next_search_id;
+                next_search_id += 1;
+                // Tabs which were closed don't answer.
+                let remaining = threads
+                    .values()
+                    .filter(|thread| {
+                        thread
+                            .input
+                            .send(MessageToWorkerThread::Search {
+                                search_id,
+                                query: query.clone(),
+                            })
+                            .is_ok()
+                    })
+                    .count();
+                if remaining == 0 {
+                    ignore(output_thread_tx.send(MessageToOutputThread::CrossTabScore {
+                        scores: vec![],
+                        action,
+                    }));
+                } else {
+                    searches.insert(
+                        search_id,
+                        Search {
+                            focused_tab_id: tab_id,
+                            remaining,
+                            scores: vec![],
+                            action,
+                            started: Instant::now(),
+                        },
+                    );
+                }
+            }
+            MessageToParentThread::MessageFromWorkerThread(
+                MessageFromWorkerThread::SearchScore {
+                    search_id,
+                    tab_id,
+                    scores,
+                },
+            ) => {
+                if let Entry::Occupied(mut entry) = searches.entry(search_id) {
+                    let search = entry.get_mut();
+                    let weight = if tab_id == search.focused_tab_id {
+                        1.0
+                    } else {
+                        context.config.other_tab_weight
+                    };
+                    search.scores.extend(
+                        scores
+                            .into_iter()
+                            .map(|(document_id, score)| ((tab_id, document_id), weight * score)),
+                    );
+                    search.remaining -= 1;
+                    if search.remaining == 0 {
+                        let search = entry.remove();
+                        ignore(output_thread_tx.send(MessageToOutputThread::CrossTabScore {
+                            scores: search.scores,
+                            action: search.action,
+                        }));
+                    }
+                }
+            }
             MessageToParentThread::MessageFromWorkerThread(MessageFromWorkerThread::Score {
                 tab_id,
                 scores,
-            }) => ignore(output_thread_tx.send(MessageToOutputThread::Score { tab_id, scores })),
+                action,
+            }) => ignore(output_thread_tx.send(MessageToOutputThread::Score {
+                tab_id,
+                scores,
+                action,
+            })),
+            MessageToParentThread::MessageFromBrowser(MessageFromBrowser::LoadPage {
+                tab_id,
+                url,
+            }) => ignore(
+                get_or_spawn_thread(&mut threads, tab_id, &parent_thread_tx, &context)
+                    .input
+                    .send(MessageToWorkerThread::LoadPage { url }),
+            ),
             MessageToParentThread::MessageFromBrowser(MessageFromBrowser::UpdateIndex {
                 tab_id,
                 updated,
                 removed,
+                complete,
+                layouts,
             }) => ignore(
-                get_or_spawn_thread(&mut threads, tab_id, &parent_thread_tx)
+                get_or_spawn_thread(&mut threads, tab_id, &parent_thread_tx, &context)
                     .input
-                    .send(MessageToWorkerThread::UpdateIndex { updated, removed }),
+                    .send(MessageToWorkerThread::UpdateIndex {
+                        updated,
+                        removed,
+                        complete,
+                        layouts,
+                    }),
             ),
-            // TODO(kvakil): only update Talon if this is actually the active tab.
+            // Tabs in the background (say, ones just opened by "surf new") load pages
+            // too, but Talon should keep listening for the tab in front.
             MessageToParentThread::MessageFromWorkerThread(
                 MessageFromWorkerThread::UpdateTalonRequest { tab_id, words },
-            ) => ignore(
-                talon_thread_tx.send(MessageToTalonThread::UpdateTalonRequest { tab_id, words }),
+            ) => {
+                if is_focused(focused_tab_id, tab_id) {
+                    ignore(
+                        talon_thread_tx.send(MessageToTalon::UpdateTalonRequest { tab_id, words }),
+                    )
+                }
+            }
+            MessageToParentThread::MessageFromWorkerThread(
+                MessageFromWorkerThread::UpdateTalonDelta {
+                    tab_id,
+                    added,
+                    removed,
+                },
+            ) => {
+                if is_focused(focused_tab_id, tab_id) {
+                    ignore(talon_thread_tx.send(MessageToTalon::UpdateTalonDelta {
+                        tab_id,
+                        added,
+                        removed,
+                    }))
+                }
+            }
+            MessageToParentThread::MessageFromTalonThread(MessageFromTalon::ShowHints {
+                tab_id,
+            }) => ignore(
+                get_or_spawn_thread(&mut threads, tab_id, &parent_thread_tx, &context)
+                    .input
+                    .send(MessageToWorkerThread::ShowHints {}),
             ),
-            MessageToParentThread::MessageFromBrowser(MessageFromBrowser::CloseTab { tab_id }) => {
+            MessageToParentThread::MessageFromTalonThread(MessageFromTalon::QueryLabel {
+                tab_id,
+                label,
+                action,
+            }) => ignore(
+                get_or_spawn_thread(&mut threads, tab_id, &parent_thread_tx, &context)
+                    .input
+                    .send(MessageToWorkerThread::QueryLabel { label, action }),
+            ),
+            MessageToParentThread::MessageFromWorkerThread(MessageFromWorkerThread::Hints {
+                tab_id,
+                hints,
+            }) => {
+                let labels = hints.iter().map(|(_, label)| label.clone()).collect();
+                ignore(talon_thread_tx.send(MessageToTalon::UpdateHints { tab_id, labels }));
+                ignore(output_thread_tx.send(MessageToOutputThread::Hints { tab_id, hints }));
+            }
+            // Talon may not know which tab is in front, so send it that tab's words.
+            MessageToParentThread::MessageFromTalonThread(MessageFromTalon::Resync { tab_id }) => {
+                let tab_id = focused_tab_id.unwrap_or(tab_id);
                 ignore(
-                    get_or_spawn_thread(&mut threads, tab_id, &parent_thread_tx)
+                    get_or_spawn_thread(&mut threads, tab_id, &parent_thread_tx, &context)
                         .input
-                        .send(MessageToWorkerThread::CloseTab {}),
+                        .send(MessageToWorkerThread::FocusTab {}),
                 )
             }
+            // Forget the worker, so that later searches don't wait on it. It answers
+            // whatever it was sent before it goes.
+            MessageToParentThread::MessageFromBrowser(MessageFromBrowser::CloseTab { tab_id }) => {
+                if let Some(thread) = threads.remove(&tab_id) {
+                    ignore(thread.input.send(MessageToWorkerThread::CloseTab {}))
+                }
+            }
         }
     });
 
-    return parent_thread_tx_for_return;
+    parent_thread_tx_for_return
 }
 
-#[derive(Serialize, Deserialize)]
-struct MessageToBrowser {
-    #[serde(rename = "tabId")]
-    tab_id: TabId,
-    best: Vec<DocumentId>,
+// The Talon clients connected to the socket, each of which is sent every message for
+// Talon. Writes are locked per client, so that frames don't interleave.
+type TalonClients = Arc<Mutex<Vec<Arc<Mutex<UnixStream>>>>>;
+
+// A client which takes longer than this to read a frame is dropped, rather than
+// holding up the others.
+const TALON_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn send_to_talon_clients(clients: &TalonClients, frame: &[u8]) {
+    clients
+        .lock()
+        .unwrap()
+        .retain(|client| write_frame(&mut *client.lock().unwrap(), frame).is_ok());
+}
+
+/// Reads the message Talon wrote at `path`, unless it was already read (a single write
+/// can raise several events), or can't be read or understood.
+fn read_talon_output(path: &Path, last_sequence: &mut Option<u64>) -> Option<MessageFromTalon> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(error) => {
+            eprintln!("talon: not reading {}: {}", path.display(), error);
+            return None;
+        }
+    };
+    let SequencedMessageFromTalon { sequence, message } = match serde_json::from_slice(&contents) {
+        Ok(message) => message,
+        Err(error) => {
+            eprintln!("talon: ignoring {}: {}", path.display(), error);
+            return None;
+        }
+    };
+    if let Some(sequence) = sequence {
+        if last_sequence.is_some_and(|last_sequence| sequence <= last_sequence) {
+            return None;
+        }
+        *last_sequence = Some(sequence);
+    }
+    Some(message)
+}
+
+/// Accepts Talon clients on `listener` until it fails, each on its own thread.
+fn serve_talon_socket(
+    listener: UnixListener,
+    clients: TalonClients,
+    parent_thread_tx: mpsc::Sender<MessageToParentThread>,
+) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                eprintln!("talon: not accepting clients: {}", error);
+                return;
+            }
+        };
+        let writer = match stream.try_clone() {
+            Ok(writer) => writer,
+            Err(error) => {
+                eprintln!("talon: dropping client: {}", error);
+                continue;
+            }
+        };
+        ignore(writer.set_write_timeout(Some(TALON_WRITE_TIMEOUT)));
+        let writer = Arc::new(Mutex::new(writer));
+        clients.lock().unwrap().push(writer.clone());
+        let parent_thread_tx = parent_thread_tx.clone();
+        thread::spawn(move || serve_talon_client(stream, writer, parent_thread_tx));
+    }
+}
+
+/// Passes the requests from one Talon client to the parent thread, acknowledging each,
+/// until the client disconnects.
+fn serve_talon_client(
+    stream: UnixStream,
+    writer: Arc<Mutex<UnixStream>>,
+    parent_thread_tx: mpsc::Sender<MessageToParentThread>,
+) {
+    let mut reader = io::BufReader::new(&stream);
+    loop {
+        let frame = match read_frame(&mut reader) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(error) => {
+                eprintln!("talon: dropping client: {}", error);
+                break;
+            }
+        };
+        let (id, error) = match serde_json::from_slice::<TalonRequest>(&frame) {
+            Ok(TalonRequest { id, message }) => {
+                if parent_thread_tx
+                    .send(MessageToParentThread::MessageFromTalonThread(message))
+                    .is_err()
+                {
+                    break;
+                }
+                (id, None)
+            }
+            // Reject requests we don't understand, if we can tell the client which.
+            Err(error) => match serde_json::from_slice::<TalonRequestId>(&frame) {
+                Ok(TalonRequestId { id }) => (id, Some(error.to_string())),
+                Err(_) => {
+                    eprintln!("talon: dropping client: {}", error);
+                    break;
+                }
+            },
+        };
+        let ack = serde_json::to_vec(&TalonAck { ack: id, error }).unwrap();
+        if write_frame(&mut *writer.lock().unwrap(), &ack).is_err() {
+            break;
+        }
+    }
+    // So that the next message for Talon drops the client.
+    ignore(stream.shutdown(Shutdown::Both));
 }
 
 use serde::Serialize;
@@ -371,38 +853,119 @@ fn read_browser_message(input: &mut io::StdinLock) -> std::vec::Vec<u8> {
     let length = input.read_u32::<NativeEndian>().unwrap();
     let mut message = input.take(length as u64);
     let mut buffer = Vec::with_capacity(length as usize);
-    message.read_to_end(&mut buffer);
-    return buffer;
+    ignore(message.read_to_end(&mut buffer));
+    buffer
+}
+
+/// Tells the parent thread whenever the aliases file at `aliases_path` changes, until the
+/// returned watcher is dropped. Editors often save by replacing the file, so this
+/// watches the directory it is in, and ignores the other files there.
+fn watch_aliases(
+    aliases_path: PathBuf,
+    parent_thread_tx: mpsc::Sender<MessageToParentThread>,
+) -> RecommendedWatcher {
+    let (aliases_changed_tx, aliases_changed_rx) = mpsc::channel();
+    let mut aliases_watcher: RecommendedWatcher = Watcher::new_raw(aliases_changed_tx).unwrap();
+    if let Some(config_directory) = aliases_path.parent() {
+        ignore(aliases_watcher.watch(config_directory, RecursiveMode::NonRecursive));
+    }
+    thread::spawn(move || {
+        while let Ok(event) = aliases_changed_rx.recv() {
+            if event.path.as_ref() == Some(&aliases_path) {
+                ignore(parent_thread_tx.send(MessageToParentThread::AliasesChanged));
+            }
+        }
+    });
+    aliases_watcher
 }
 
 // TODO(kvakil): split this function up.
 fn main() {
-    let (talon_thread_tx, talon_thread_rx) = mpsc::channel::<MessageToTalonThread>();
+    let (talon_thread_tx, talon_thread_rx) = mpsc::channel::<MessageToTalon>();
     let (output_thread_tx, output_thread_rx) = mpsc::channel::<MessageToOutputThread>();
-    let parent_thread_tx = spawn_parent_thread(output_thread_tx, talon_thread_tx);
     let xdg_dirs = xdg::BaseDirectories::with_prefix("voicesurf").unwrap();
+    let config = config::load(&xdg_dirs);
+    let confidence = config.confidence;
+    // Snapshots are only a cache, so carry on without them if need be.
+    let snapshot_store = match xdg_dirs.create_cache_directory("snapshots") {
+        Ok(directory) => {
+            let snapshot_store = SnapshotStore::new(directory);
+            if let Err(error) = snapshot_store.prune(MAX_SNAPSHOTS, MAX_SNAPSHOT_AGE) {
+                eprintln!("snapshot: not pruning snapshots: {}", error);
+            }
+            Some(snapshot_store)
+        }
+        Err(error) => {
+            eprintln!("snapshot: not saving snapshots: {}", error);
+            None
+        }
+    };
+    let click_history = match xdg_dirs.place_data_file(CLICK_HISTORY_FILENAME) {
+        Ok(path) => ClickHistory::load(path, config.click_half_life_days * SECONDS_PER_DAY),
+        Err(error) => {
+            eprintln!("history: not saving clicks: {}", error);
+            ClickHistory::new(config.click_half_life_days * SECONDS_PER_DAY)
+        }
+    };
+    let aliases_path = xdg_dirs.place_config_file(ALIASES_FILENAME);
+    let aliases = match &aliases_path {
+        Ok(path) => Aliases::load(path.clone()),
+        Err(error) => {
+            eprintln!("aliases: not reading aliases: {}", error);
+            Aliases::default()
+        }
+    };
+    let parent_thread_tx = spawn_parent_thread(
+        output_thread_tx,
+        talon_thread_tx,
+        WorkerContext::new(config, snapshot_store, click_history, aliases),
+    );
+
+    let _aliases_watcher = aliases_path
+        .ok()
+        .map(|aliases_path| watch_aliases(aliases_path, parent_thread_tx.clone()));
     let talon_input_directory = xdg_dirs.create_runtime_directory("input").unwrap();
     let talon_preinput_directory = xdg_dirs.create_runtime_directory("preinput").unwrap();
 
-    // Talon updating thread
-    thread::spawn(move || {
-        let talon_input_filename = talon_input_directory.join(VERSION);
-        let talon_preinput_filename = talon_preinput_directory.join(VERSION);
-        let talon_input_path = talon_input_filename.as_path();
-        let talon_preinput_path = talon_preinput_filename.as_path();
-        let mut talon_preinput_file = File::create(talon_preinput_path).unwrap();
-        loop {
-            match talon_thread_rx.recv() {
-                Ok(message) => {
-                    talon_preinput_file.seek(io::SeekFrom::Start(0)).unwrap();
-                    talon_preinput_file.set_len(0).unwrap();
-                    talon_preinput_file
-                        .write_all(serde_json::to_string(&message).unwrap().as_bytes())
-                        .unwrap();
-                    talon_preinput_file.sync_all().unwrap();
-                    fs::copy(talon_preinput_path, talon_input_path).unwrap();
+    // Talon socket thread. Clients which can't connect (say, an older Talon script) use
+    // the files below instead.
+    let talon_clients = TalonClients::default();
+    match xdg_dirs.place_runtime_file(format!("talon-{}.sock", VERSION)) {
+        Ok(talon_socket_path) => {
+            // A socket left over from an earlier host would stop us binding it.
+            ignore(fs::remove_file(&talon_socket_path));
+            match UnixListener::bind(&talon_socket_path) {
+                Ok(listener) => {
+                    let talon_clients = talon_clients.clone();
+                    let parent_thread_tx = parent_thread_tx.clone();
+                    thread::spawn(move || {
+                        serve_talon_socket(listener, talon_clients, parent_thread_tx)
+                    });
                 }
-                Err(_) => break,
+                Err(error) => eprintln!("talon: not listening on a socket: {}", error),
+            }
+        }
+        Err(error) => eprintln!("talon: not listening on a socket: {}", error),
+    }
+
+    // Talon updating thread. Each message is written to the preinput directory and
+    // renamed into place, so that Talon never reads half of one.
+    thread::spawn(move || {
+        let talon_input_path = talon_input_directory.join(VERSION);
+        let talon_preinput_path = talon_preinput_directory.join(VERSION);
+        let mut sequence = 0;
+        while let Ok(message) = talon_thread_rx.recv() {
+            sequence += 1;
+            let message = serde_json::to_string(&SequencedMessageToTalon {
+                sequence,
+                message: &message,
+            })
+            .unwrap();
+            send_to_talon_clients(&talon_clients, message.as_bytes());
+            if let Err(error) =
+                write_atomically(&talon_preinput_path, &talon_input_path, message.as_bytes())
+            {
+                eprintln!("talon: not writing message {}: {}", sequence, error);
             }
         }
     });
@@ -411,56 +974,46 @@ fn main() {
     let (talon_receive_tx, talon_receive_rx) = mpsc::channel();
     let mut watcher: RecommendedWatcher = Watcher::new_raw(talon_receive_tx).unwrap();
     let talon_output_directory = xdg_dirs.create_runtime_directory("output").unwrap();
-    let talon_output_filename = talon_output_directory.join(VERSION);
+    let talon_output_path = talon_output_directory.join(VERSION);
     let parent_thread_tx_for_talon = parent_thread_tx.clone();
     watcher
         .watch(talon_output_directory, RecursiveMode::NonRecursive)
         .unwrap();
     thread::spawn(move || {
-        let talon_output_path = talon_output_filename.as_path();
-        loop {
-            match talon_receive_rx.recv() {
-                // TODO(kvakil): scope this event?
-                Ok(_event) => {
-                    let mut talon_output_file = File::open(talon_output_path).unwrap();
-                    let mut buffer = String::new();
-                    talon_output_file.read_to_string(&mut buffer).unwrap();
-                    let message: MessageFromTalonThread =
-                        serde_json::from_slice(buffer.as_bytes()).unwrap();
+        let mut last_sequence = None;
+        while let Ok(event) = talon_receive_rx.recv() {
+            // Talon writes a temporary file next to the output file first.
+            if event.path.as_ref() != Some(&talon_output_path) {
+                continue;
+            }
+            if let Some(message) = read_talon_output(&talon_output_path, &mut last_sequence) {
+                ignore(
                     parent_thread_tx_for_talon
-                        .send(MessageToParentThread::MessageFromTalonThread(message))
-                        .unwrap();
-                }
-                Err(_) => break,
+                        .send(MessageToParentThread::MessageFromTalonThread(message)),
+                );
             }
         }
     });
 
     // Output thread
-    thread::spawn(move || loop {
-        match output_thread_rx.recv() {
-            Ok(MessageToOutputThread::Score { tab_id, scores }) => {
-                let mut best_by_score = scores.into_iter().collect::<Vec<(DocumentId, Score)>>();
-                // TODO(kvakil): this can be more efficient -- we don't need the whole sort
-                // obviously
-                best_by_score.sort_by(|(doc_id0, score0), (doc_id1, score1)| {
-                    score1.partial_cmp(score0).unwrap_or(doc_id0.cmp(doc_id1))
-                });
-                // Send top 10 arbitrarily.
-                // TODO(kvakil): maybe just send all, or all above a threshold, or only send
-                // the few which are "far better"?
-                // TODO(kvakil): maybe make a floor here, so we don't send anything if all the
-                // choices are really bad.
-                best_by_score.truncate(10);
-                // TODO(kvakil): structured logging?
-                eprintln!("output: dumping to browser");
-                dump(MessageToBrowser {
+    thread::spawn(move || {
+        while let Ok(message) = output_thread_rx.recv() {
+            let message = match message {
+                MessageToOutputThread::Score {
                     tab_id,
-                    best: best_by_score.iter().map(|(id, _score)| *id).collect(),
-                })
-                .unwrap();
-            }
-            Err(_) => break,
+                    scores,
+                    action,
+                } => rank_results(tab_id, scores, action, &confidence),
+                MessageToOutputThread::CrossTabScore { scores, action } => {
+                    rank_cross_tab_results(scores, action, &confidence)
+                }
+                MessageToOutputThread::Hints { tab_id, hints } => {
+                    MessageToBrowser::Hints { tab_id, hints }
+                }
+            };
+            // TODO(kvakil): structured logging?
+            eprintln!("output: dumping to browser");
+            dump(message).unwrap();
         }
     });
 
@@ -483,117 +1036,589 @@ fn main() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use voicesurf_core::DocumentContent;
+
+    fn test_context(snapshot_store: Option<SnapshotStore>) -> WorkerContext {
+        test_context_with_aliases(snapshot_store, Aliases::default())
+    }
+
+    fn test_context_with_aliases(
+        snapshot_store: Option<SnapshotStore>,
+        aliases: Aliases,
+    ) -> WorkerContext {
+        WorkerContext::new(
+            Config::default(),
+            snapshot_store,
+            ClickHistory::new(0),
+            aliases,
+        )
+    }
 
     #[test]
-    fn create_index_and_query() {
-        let documents: Vec<String> = vec![
-            String::from("this is sample").to_string(),
-            String::from("this is another another example example example").to_string(),
-        ];
+    fn create_index_and_query_threaded() {
+        let tab_id = 3;
+        let (tx, rx) = mpsc::channel();
+        let (txp, _rxp) = mpsc::channel();
+        let parent_thread_tx = spawn_parent_thread(tx, txp, test_context(None));
+        parent_thread_tx
+            .send(MessageToParentThread::MessageFromBrowser(
+                MessageFromBrowser::FocusTab { tab_id },
+            ))
+            .unwrap();
+        send_from_browser(
+            &parent_thread_tx,
+            update_index(
+                tab_id,
+                &[
+                    "this is sample",
+                    "this is another another example example example",
+                ],
+            ),
+        );
+        parent_thread_tx
+            .send(MessageToParentThread::MessageFromTalonThread(
+                MessageFromTalon::Query {
+                    tab_id,
+                    query: "example".to_string(),
+                    action: Action::Click,
+                },
+            ))
+            .unwrap();
+        match rx.recv() {
+            Ok(MessageToOutputThread::Score {
+                scores: scores_example,
+                ..
+            }) => {
+                // 3/7 * ln(1 + 2/1), as in the core crate's create_index_and_query.
+                assert_eq!(scores_example.get(&0).cloned().unwrap_or(0.0), 0.0);
+                assert_eq!(scores_example.get(&1).cloned().unwrap_or(0.0), 0.4708339);
+            }
+            _ => panic!(),
+        }
+    }
 
-        let tfidf_index: TfidfIndex = make_index(
-            documents
+    /// A complete update of the tab, with a document for each of `texts`.
+    fn update_index(tab_id: TabId, texts: &[&str]) -> MessageFromBrowser {
+        update_index_with_layouts(tab_id, texts, vec![])
+    }
+
+    fn update_index_with_layouts(
+        tab_id: TabId,
+        texts: &[&str],
+        layouts: Vec<(DocumentId, Layout)>,
+    ) -> MessageFromBrowser {
+        MessageFromBrowser::UpdateIndex {
+            tab_id,
+            updated: texts
                 .iter()
                 .enumerate()
-                .map(|(id, doc)| (id, doc.to_string()))
+                .map(|(id, text)| (id, DocumentContent::Text(text.to_string())))
                 .collect(),
-        );
+            removed: vec![],
+            complete: true,
+            layouts,
+        }
+    }
 
-        let scores_this_is = score(&tfidf_index, "this is".to_string());
-        assert_eq!(scores_this_is.get(&0).cloned().unwrap_or(0.0), 0.0);
-        assert_eq!(scores_this_is.get(&1).cloned().unwrap_or(0.0), 0.0);
+    fn send_from_browser(
+        parent_thread_tx: &mpsc::Sender<MessageToParentThread>,
+        message: MessageFromBrowser,
+    ) {
+        parent_thread_tx
+            .send(MessageToParentThread::MessageFromBrowser(message))
+            .unwrap();
+    }
 
-        let scores_example = score(&tfidf_index, "example".to_string());
-        assert_eq!(scores_example.get(&0).cloned().unwrap_or(0.0), 0.0);
-        assert_eq!(scores_example.get(&1).cloned().unwrap_or(0.0), 0.17377077);
+    fn query(
+        parent_thread_tx: &mpsc::Sender<MessageToParentThread>,
+        rx: &mpsc::Receiver<MessageToOutputThread>,
+        tab_id: TabId,
+        query: &str,
+    ) -> ScoreResult {
+        parent_thread_tx
+            .send(MessageToParentThread::MessageFromTalonThread(
+                MessageFromTalon::Query {
+                    tab_id,
+                    query: query.to_string(),
+                    action: Action::Click,
+                },
+            ))
+            .unwrap();
+        match rx.recv() {
+            Ok(MessageToOutputThread::Score { scores, .. }) => scores,
+            _ => panic!(),
+        }
     }
 
     #[test]
-    fn create_index_and_update() {
-        let documents: Vec<String> = vec![
-            String::from("this is sample").to_string(),
-            String::from("this will get overwritten").to_string(),
-        ];
+    fn snapshots_are_warm_loaded_and_reconciled() {
+        let directory = tempfile::tempdir().unwrap();
+        let snapshot_store = SnapshotStore::new(directory.path().to_path_buf());
+        let tab_id = 5;
+        let url = "https://mail.example.com/".to_string();
 
-        let mut tfidf_index: TfidfIndex = make_index(
-            documents
-                .iter()
-                .enumerate()
-                .map(|(id, doc)| (id, doc.to_string()))
-                .collect(),
-        );
-
-        update_index(
-            &mut tfidf_index,
-            1,
-            &String::from("will be overwrriten this is").to_string(),
+        let (tx, rx) = mpsc::channel();
+        let (txp, _rxp) = mpsc::channel();
+        let parent_thread_tx =
+            spawn_parent_thread(tx, txp, test_context(Some(snapshot_store.clone())));
+        send_from_browser(
+            &parent_thread_tx,
+            MessageFromBrowser::LoadPage {
+                tab_id,
+                url: url.clone(),
+            },
         );
-        update_index(
-            &mut tfidf_index,
-            1,
-            &String::from("will be overwrriten this is example").to_string(),
+        send_from_browser(
+            &parent_thread_tx,
+            update_index(tab_id, &["Inbox", "Sent mail"]),
         );
-        update_index(
-            &mut tfidf_index,
-            1,
-            &String::from("this is another another example example example").to_string(),
+        // Navigating away saves the snapshot of the old page.
+        send_from_browser(
+            &parent_thread_tx,
+            MessageFromBrowser::LoadPage {
+                tab_id,
+                url: "https://example.com/".to_string(),
+            },
         );
+        assert!(query(&parent_thread_tx, &rx, tab_id, "inbox").is_empty());
 
-        let scores_this_is = score(&tfidf_index, "this is".to_string());
-        assert_eq!(scores_this_is.get(&0).cloned().unwrap_or(0.0), 0.0);
-        assert_eq!(scores_this_is.get(&1).cloned().unwrap_or(0.0), 0.0);
+        // A restarted host can search the page before the browser sends anything.
+        let (tx, rx) = mpsc::channel();
+        let (txp, _rxp) = mpsc::channel();
+        let parent_thread_tx = spawn_parent_thread(tx, txp, test_context(Some(snapshot_store)));
+        send_from_browser(
+            &parent_thread_tx,
+            MessageFromBrowser::LoadPage { tab_id, url },
+        );
+        assert!(query(&parent_thread_tx, &rx, tab_id, "inbox")[&0] > 0.0);
 
-        let scores_example = score(&tfidf_index, "example".to_string());
-        assert_eq!(scores_example.get(&0).cloned().unwrap_or(0.0), 0.0);
-        assert_eq!(scores_example.get(&1).cloned().unwrap_or(0.0), 0.17377077);
+        send_from_browser(&parent_thread_tx, update_index(tab_id, &["Drafts"]));
+        assert!(query(&parent_thread_tx, &rx, tab_id, "inbox").is_empty());
+        assert!(query(&parent_thread_tx, &rx, tab_id, "mail").is_empty());
+        assert!(query(&parent_thread_tx, &rx, tab_id, "drafts").contains_key(&0));
     }
 
     #[test]
-    fn create_index_and_query_threaded() {
-        let documents_data: Vec<String> = vec![
-            String::from("this is sample").to_string(),
-            String::from("this is another another example example example").to_string(),
+    fn query_all_tabs_penalizes_other_tabs() {
+        let (tx, rx) = mpsc::channel();
+        let (txp, _rxp) = mpsc::channel();
+        let parent_thread_tx = spawn_parent_thread(tx, txp, test_context(None));
+        let pages: &[(TabId, &[&str])] = &[
+            (1, &["Inbox", "Pull requests", "Settings"]),
+            (2, &["Pull requests", "Issues", "Wiki"]),
+            (3, &["Home", "About", "Blog"]),
         ];
-        let documents = documents_data
-            .iter()
-            .enumerate()
-            .map(|(id, doc)| (id, doc.to_string()))
-            .collect();
-        let tab_id = 3;
+        for (tab_id, texts) in pages {
+            send_from_browser(&parent_thread_tx, update_index(*tab_id, texts));
+        }
+        parent_thread_tx
+            .send(MessageToParentThread::MessageFromTalonThread(
+                MessageFromTalon::QueryAllTabs {
+                    tab_id: 2,
+                    query: "pull requests".to_string(),
+                    action: Action::OpenInBackgroundTab,
+                },
+            ))
+            .unwrap();
+        let scores = match rx.recv() {
+            Ok(MessageToOutputThread::CrossTabScore { scores, action }) => {
+                assert_eq!(action, Action::OpenInBackgroundTab);
+                scores
+            }
+            _ => panic!(),
+        };
+        let scores: FxHashMap<(TabId, DocumentId), Score> = scores.into_iter().collect();
+        assert_eq!(scores.len(), 2);
+        assert!((scores[&(1, 1)] - 0.5 * scores[&(2, 0)]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn closed_tabs_are_not_searched() {
         let (tx, rx) = mpsc::channel();
-        let (txp, rxp) = mpsc::channel();
-        let parent_thread_tx = spawn_parent_thread(tx, txp);
+        let (txp, _rxp) = mpsc::channel();
+        let parent_thread_tx = spawn_parent_thread(tx, txp, test_context(None));
+        for tab_id in 1..=2 {
+            send_from_browser(&parent_thread_tx, update_index(tab_id, &["Pull requests"]));
+        }
+        send_from_browser(
+            &parent_thread_tx,
+            MessageFromBrowser::CloseTab { tab_id: 2 },
+        );
         parent_thread_tx
-            .send(MessageToParentThread::MessageFromBrowser(
-                MessageFromBrowser::FocusTab { tab_id },
+            .send(MessageToParentThread::MessageFromTalonThread(
+                MessageFromTalon::QueryAllTabs {
+                    tab_id: 1,
+                    query: "pull requests".to_string(),
+                    action: Action::Click,
+                },
             ))
             .unwrap();
+        match rx.recv_timeout(SEARCH_TIMEOUT / 2) {
+            Ok(MessageToOutputThread::CrossTabScore { scores, .. }) => {
+                assert_eq!(
+                    scores.iter().map(|(key, _)| *key).collect::<Vec<_>>(),
+                    vec![(1, 0)]
+                )
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn searches_finish_without_every_tab() {
+        let (tx, rx) = mpsc::channel();
+        let mut searches = FxHashMap::<SearchId, Search>::default();
+        let search = |started| Search {
+            focused_tab_id: 1,
+            remaining: 1,
+            scores: vec![((1, 0), 0.5)],
+            action: Action::Click,
+            started,
+        };
+        searches.insert(0, search(Instant::now() - 2 * SEARCH_TIMEOUT));
+        searches.insert(1, search(Instant::now()));
+        expire_searches(&mut searches, &tx);
+        match rx.try_recv() {
+            Ok(MessageToOutputThread::CrossTabScore { scores, .. }) => {
+                assert_eq!(scores, vec![((1, 0), 0.5)])
+            }
+            _ => panic!(),
+        }
+        assert!(rx.try_recv().is_err());
+        assert_eq!(searches.keys().collect::<Vec<_>>(), vec![&1]);
+    }
+
+    #[test]
+    fn clicks_boost_the_clicked_link_on_the_same_site() {
+        let (tx, rx) = mpsc::channel();
+        let (txp, _rxp) = mpsc::channel();
+        let parent_thread_tx = spawn_parent_thread(tx, txp, test_context(None));
+        let tab_id = 1;
+        let load = |url: &str| {
+            send_from_browser(
+                &parent_thread_tx,
+                MessageFromBrowser::LoadPage {
+                    tab_id,
+                    url: url.to_string(),
+                },
+            );
+            send_from_browser(
+                &parent_thread_tx,
+                update_index(tab_id, &["Inbox mail", "Sent mail", "Drafts"]),
+            );
+        };
+
+        load("https://mail.example.com/");
+        let before = query(&parent_thread_tx, &rx, tab_id, "mail");
+        send_from_browser(
+            &parent_thread_tx,
+            MessageFromBrowser::Clicked { tab_id, id: 0 },
+        );
+
+        // The document ids of a reloaded page needn't match, but the link does.
+        load("https://mail.example.com/u/1");
+        let after = query(&parent_thread_tx, &rx, tab_id, "mail");
+        assert_eq!(before[&0], before[&1]);
+        assert!(after[&0] - before[&0] > 0.2);
+        assert_eq!(after.get(&1), before.get(&1));
+
+        load("https://example.com/");
+        assert_eq!(query(&parent_thread_tx, &rx, tab_id, "mail"), before);
+
+        load("https://mail.example.com/");
         parent_thread_tx
-            .send(MessageToParentThread::MessageFromBrowser(
-                MessageFromBrowser::UpdateIndex {
+            .send(MessageToParentThread::MessageFromTalonThread(
+                MessageFromTalon::ForgetClicks { tab_id },
+            ))
+            .unwrap();
+        assert_eq!(query(&parent_thread_tx, &rx, tab_id, "mail"), before);
+    }
+
+    #[test]
+    fn aliases_are_reloaded_when_changed() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join(ALIASES_FILENAME);
+        std::fs::write(
+            &path,
+            r#"{"example.com": [{"say": "next page", "text": "»"}]}"#,
+        )
+        .unwrap();
+        let (tx, rx) = mpsc::channel();
+        let (txp, txp_rx) = mpsc::channel();
+        let parent_thread_tx = spawn_parent_thread(
+            tx,
+            txp,
+            test_context_with_aliases(None, Aliases::load(path.clone())),
+        );
+        let tab_id = 1;
+        send_from_browser(
+            &parent_thread_tx,
+            MessageFromBrowser::LoadPage {
+                tab_id,
+                url: "https://news.example.com/".to_string(),
+            },
+        );
+        send_from_browser(&parent_thread_tx, update_index(tab_id, &["»", "Past"]));
+        assert!(query(&parent_thread_tx, &rx, tab_id, "next page").contains_key(&0));
+
+        std::fs::write(&path, r#"{"example.com": [{"say": "more", "text": "»"}]}"#).unwrap();
+        parent_thread_tx
+            .send(MessageToParentThread::AliasesChanged)
+            .unwrap();
+        assert!(query(&parent_thread_tx, &rx, tab_id, "next page").is_empty());
+        assert!(query(&parent_thread_tx, &rx, tab_id, "more").contains_key(&0));
+        // Talon hears about the new words.
+        let deltas: Vec<MessageToTalon> = txp_rx.try_iter().collect();
+        assert!(deltas.iter().any(|message| match message {
+            MessageToTalon::UpdateTalonDelta { added, .. } => added.contains_key("more"),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn aliases_are_watched_on_disk() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join(ALIASES_FILENAME);
+        let (parent_thread_tx, parent_thread_rx) = mpsc::channel();
+        let _aliases_watcher = watch_aliases(path.clone(), parent_thread_tx);
+        let changed = |timeout| match parent_thread_rx.recv_timeout(timeout) {
+            Ok(MessageToParentThread::AliasesChanged) => true,
+            Ok(_) => panic!(),
+            Err(_) => false,
+        };
+
+        // Other files next to it, like the configuration, don't count.
+        fs::write(directory.path().join("config.json"), "{}").unwrap();
+        assert!(!changed(Duration::from_millis(200)));
+
+        // Replacing the file counts, as editors often save that way.
+        write_atomically(
+            &directory.path().join("aliases.json.tmp"),
+            &path,
+            r#"{"*": [{"say": "more", "text": "»"}]}"#.as_bytes(),
+        )
+        .unwrap();
+        assert!(changed(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn only_the_focused_tab_updates_talon() {
+        let (tx, rx) = mpsc::channel();
+        let (txp, txp_rx) = mpsc::channel();
+        let parent_thread_tx = spawn_parent_thread(tx, txp, test_context(None));
+        let load = |tab_id, text: &str| {
+            send_from_browser(
+                &parent_thread_tx,
+                MessageFromBrowser::LoadPage {
                     tab_id,
-                    updated: documents,
-                    removed: vec![],
+                    url: "https://example.com/".to_string(),
                 },
+            );
+            send_from_browser(&parent_thread_tx, update_index(tab_id, &[text]));
+        };
+        send_from_browser(
+            &parent_thread_tx,
+            MessageFromBrowser::FocusTab { tab_id: 1 },
+        );
+        load(1, "Inbox");
+        // Say, a link opened in the background with "surf new".
+        load(2, "Drafts");
+        // Wait for both tabs to be indexed.
+        query(&parent_thread_tx, &rx, 1, "inbox");
+        query(&parent_thread_tx, &rx, 2, "drafts");
+        let tab_ids = |txp_rx: &mpsc::Receiver<MessageToTalon>| -> FxHashSet<TabId> {
+            txp_rx
+                .try_iter()
+                .map(|message| match message {
+                    MessageToTalon::UpdateTalonRequest { tab_id, .. }
+                    | MessageToTalon::UpdateTalonDelta { tab_id, .. }
+                    | MessageToTalon::UpdateHints { tab_id, .. } => tab_id,
+                })
+                .collect()
+        };
+        assert_eq!(tab_ids(&txp_rx), std::iter::once(1).collect());
+
+        send_from_browser(
+            &parent_thread_tx,
+            MessageFromBrowser::FocusTab { tab_id: 2 },
+        );
+        query(&parent_thread_tx, &rx, 2, "drafts");
+        assert_eq!(tab_ids(&txp_rx), std::iter::once(2).collect());
+    }
+
+    #[test]
+    fn hint_labels_resolve_to_documents_on_screen() {
+        let (tx, rx) = mpsc::channel();
+        let (txp, txp_rx) = mpsc::channel();
+        let parent_thread_tx = spawn_parent_thread(tx, txp, test_context(None));
+        let tab_id = 1;
+        let layout = |visible, top| Layout {
+            visible,
+            top,
+            left: 0.0,
+            width: 40.0,
+            height: 20.0,
+            font_size: 16.0,
+        };
+        send_from_browser(
+            &parent_thread_tx,
+            update_index_with_layouts(
+                tab_id,
+                &["Reply", "Reply", "Reply"],
+                vec![
+                    (0, layout(true, 500.0)),
+                    (1, layout(false, 3000.0)),
+                    (2, layout(true, 100.0)),
+                ],
+            ),
+        );
+        parent_thread_tx
+            .send(MessageToParentThread::MessageFromTalonThread(
+                MessageFromTalon::ShowHints { tab_id },
             ))
             .unwrap();
+        match rx.recv() {
+            Ok(MessageToOutputThread::Hints { hints, .. }) => {
+                assert_eq!(hints, vec![(0, "bat".to_string()), (2, "air".to_string())])
+            }
+            _ => panic!(),
+        }
+        assert!(txp_rx.try_iter().any(|message| match message {
+            MessageToTalon::UpdateHints { labels, .. } => labels.len() == 2,
+            _ => false,
+        }));
+
+        let query_label = |label: &str| {
+            parent_thread_tx
+                .send(MessageToParentThread::MessageFromTalonThread(
+                    MessageFromTalon::QueryLabel {
+                        tab_id,
+                        label: label.to_string(),
+                        action: Action::Click,
+                    },
+                ))
+                .unwrap();
+            match rx.recv() {
+                Ok(MessageToOutputThread::Score { scores, .. }) => scores,
+                _ => panic!(),
+            }
+        };
+        assert_eq!(query_label("bat").keys().collect::<Vec<_>>(), vec![&0]);
+        assert!(query_label("cap").is_empty());
+    }
+
+    #[test]
+    fn alternatives_are_combined_and_credited_for_clicks() {
+        let (tx, rx) = mpsc::channel();
+        let (txp, _rxp) = mpsc::channel();
+        let parent_thread_tx = spawn_parent_thread(tx, txp, test_context(None));
+        let tab_id = 1;
+        send_from_browser(
+            &parent_thread_tx,
+            MessageFromBrowser::LoadPage {
+                tab_id,
+                url: "https://mail.example.com/".to_string(),
+            },
+        );
+        send_from_browser(
+            &parent_thread_tx,
+            update_index(tab_id, &["Inbox mail", "Sent mail", "Drafts"]),
+        );
+        let mail = query(&parent_thread_tx, &rx, tab_id, "mail");
         parent_thread_tx
             .send(MessageToParentThread::MessageFromTalonThread(
-                MessageFromTalonThread::Query {
+                MessageFromTalon::QueryAlternatives {
                     tab_id,
-                    query: "example".to_string(),
+                    alternatives: vec![("zebra".to_string(), 0.6), ("mail".to_string(), 0.4)],
+                    action: Action::Click,
                 },
             ))
             .unwrap();
         match rx.recv() {
-            Ok(MessageToOutputThread::Score {
-                scores: scores_example,
-                tab_id: _,
-            }) => {
-                assert_eq!(scores_example.get(&0).cloned().unwrap_or(0.0), 0.0);
-                assert_eq!(scores_example.get(&1).cloned().unwrap_or(0.0), 0.17377077);
+            Ok(MessageToOutputThread::Score { scores, .. }) => {
+                assert_eq!(scores.len(), mail.len());
+                assert!(scores
+                    .iter()
+                    .all(|(id, score)| (score - mail[id]).abs() < 1e-6));
             }
-            Err(_) => assert!(false),
+            _ => panic!(),
         }
+
+        // The click is credited to "mail", not to "zebra".
+        send_from_browser(
+            &parent_thread_tx,
+            MessageFromBrowser::Clicked { tab_id, id: 1 },
+        );
+        let scores = query(&parent_thread_tx, &rx, tab_id, "mail");
+        assert!(scores[&1] > mail[&1]);
+    }
+
+    #[test]
+    fn talon_socket_clients_are_acknowledged_and_sent_updates() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("talon.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let clients = TalonClients::default();
+        let (parent_thread_tx, parent_thread_rx) = mpsc::channel();
+        let server_clients = clients.clone();
+        thread::spawn(move || serve_talon_socket(listener, server_clients, parent_thread_tx));
+
+        let mut first = UnixStream::connect(&path).unwrap();
+        let mut second = UnixStream::connect(&path).unwrap();
+        let request_and_ack = |client: &mut UnixStream, request: &str| {
+            write_frame(client, request.as_bytes()).unwrap();
+            String::from_utf8(read_frame(client).unwrap().unwrap()).unwrap()
+        };
+        assert_eq!(
+            request_and_ack(&mut first, r#"{"id": 1, "Resync": {"tabId": 3}}"#),
+            r#"{"ack":1}"#
+        );
+        match parent_thread_rx.recv().unwrap() {
+            MessageToParentThread::MessageFromTalonThread(MessageFromTalon::Resync { tab_id }) => {
+                assert_eq!(tab_id, 3)
+            }
+            _ => panic!(),
+        }
+        assert!(request_and_ack(&mut second, r#"{"id": 2, "Jump": {}}"#)
+            .starts_with(r#"{"ack":2,"error":"#));
+        assert!(parent_thread_rx.try_recv().is_err());
+
+        // Both clients have been accepted by now, so both are sent updates.
+        send_to_talon_clients(&clients, b"{}");
+        assert_eq!(read_frame(&mut first).unwrap().unwrap(), b"{}");
+        assert_eq!(read_frame(&mut second).unwrap().unwrap(), b"{}");
+
+        // A client which disconnects is dropped.
+        drop(first);
+        send_to_talon_clients(&clients, b"{}");
+        send_to_talon_clients(&clients, b"{}");
+        assert_eq!(clients.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn talon_output_is_read_once() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join(VERSION);
+        let write = |contents: &str| {
+            write_atomically(&directory.path().join("v0.tmp"), &path, contents.as_bytes()).unwrap()
+        };
+        let mut last_sequence = None;
+        let mut read = || read_talon_output(&path, &mut last_sequence).is_some();
+
+        assert!(!read());
+        write(r#"{"sequence": 5, "ForgetAllClicks": {}}"#);
+        assert!(read());
+        // The same write can be seen more than once.
+        assert!(!read());
+        write(r#"{"sequence": 4, "ForgetAllClicks": {}}"#);
+        assert!(!read());
+        write(r#"{"sequence": 6, "ForgetAll"#);
+        assert!(!read());
+        write(r#"{"sequence": 6, "ForgetAllClicks": {}}"#);
+        assert!(read());
+        // Older scripts don't number their messages.
+        write(r#"{"ForgetAllClicks": {}}"#);
+        assert!(read());
+        assert!(read());
     }
 }
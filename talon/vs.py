@@ -1,39 +1,225 @@
 from pathlib import Path
 from typing import List
 from talon import cron, Module, Context, fs
+from talon.grammar import Phrase
 import json
+import socket
+import os
+import struct
+import threading
+import time
 
 # TODO(kvakil): better way to get XDG_RUNTIME_DIR to Talon?
 VOICESURF_PATH = Path.home() / ".run" / "voicesurf"
+# The native host listens here. When it can't be reached, the files under
+# VOICESURF_PATH are used instead.
+SOCKET_PATH = VOICESURF_PATH / "talon-v0.sock"
 
 mod = Module()
 mod.list("hints", desc="hints from the web page")
+mod.list("surf_labels", desc="labels shown over the links on the web page")
 
 ctx = Context()
 ctx.lists["self.hints"] = {}
+ctx.lists["self.surf_labels"] = {}
 
 current_tab_id = None
+last_sequence = None
+# How each word on the page is said, mapped to the word as written.
+words = {}
+
+
+connection = None
+connection_lock = threading.Lock()
+last_request_id = 0
+# Messages written to the output file are numbered in increasing order, so that the host
+# can ignore one it reads twice. Starting from the time keeps them increasing when Talon
+# restarts.
+last_output_sequence = int(time.time() * 1000)
+# Requests sent on the socket which haven't been acknowledged yet, by id.
+pending_requests = {}
+
+
+def send_frame(sock, message):
+    frame = json.dumps(message).encode()
+    sock.sendall(struct.pack(">I", len(frame)) + frame)
+
+
+def receive_exactly(sock, length):
+    data = b""
+    while len(data) < length:
+        chunk = sock.recv(length - len(data))
+        if not chunk:
+            return None
+        data += chunk
+    return data
+
+
+def receive(sock):
+    global connection
+    try:
+        while True:
+            header = receive_exactly(sock, 4)
+            if header is None:
+                break
+            (length,) = struct.unpack(">I", header)
+            frame = receive_exactly(sock, length)
+            if frame is None:
+                break
+            message = json.loads(frame)
+            if "ack" in message:
+                request = pending_requests.pop(message["ack"], None)
+                if "error" in message:
+                    print(f"voicesurf: {request} was rejected: {message['error']}")
+            else:
+                handle_message(message)
+    except (OSError, ValueError) as error:
+        print(f"voicesurf: disconnected: {error}")
+    finally:
+        with connection_lock:
+            if connection is sock:
+                connection = None
+        sock.close()
+
+
+def connect():
+    global connection
+    with connection_lock:
+        if connection is not None:
+            return
+        sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+        try:
+            sock.connect(str(SOCKET_PATH))
+        except OSError:
+            sock.close()
+            return
+        connection = sock
+    threading.Thread(target=receive, args=(sock,), daemon=True).start()
+
+
+def write_atomically(path, message):
+    # Write next to the file and rename it into place, so that it is never read half
+    # written.
+    temporary_path = path.with_name(path.name + ".tmp")
+    with temporary_path.open("w") as fp:
+        json.dump(message, fp)
+    os.replace(temporary_path, path)
+
+
+def write_output(message):
+    global connection, last_request_id, last_output_sequence
+    with connection_lock:
+        if connection is not None:
+            last_request_id += 1
+            pending_requests[last_request_id] = message
+            try:
+                send_frame(connection, {"id": last_request_id, **message})
+                return
+            except OSError:
+                connection.close()
+                connection = None
+    last_output_sequence += 1
+    write_atomically(
+        VOICESURF_PATH / "output" / "v0", {"sequence": last_output_sequence, **message}
+    )
 
 
 @mod.action_class
 class Actions:
     def surf(hints: List[str]):
         """Surf to hint"""
-        with (VOICESURF_PATH / "output" / "v0").open("w") as fp:
-            # TODO(kvakil): use a temporary file to make this atomic?
-            json.dump(
-                {"Query": {"query": " ".join(hints), "tabId": current_tab_id}}, fp
-            )
-
-
-def update_surf(_, _2):
-    global current_tab_id
-    with (VOICESURF_PATH / "input" / "v0").open() as fp:
-        message = json.loads(fp)
-        hints = message["UpdateTalonRequest"]["words"]
+        write_output({"Query": {"query": " ".join(hints), "tabId": current_tab_id}})
+
+    def surf_action(action: str, hints: List[str]):
+        """Do something other than clicking with hint, like copying its address"""
+        write_output(
+            {
+                "Query": {
+                    "query": " ".join(hints),
+                    "tabId": current_tab_id,
+                    "action": action,
+                }
+            }
+        )
+
+    def surf_alternatives(alternatives: list):
+        """Surf to hint, given several (query, confidence) hearings of it"""
+        write_output(
+            {
+                "QueryAlternatives": {
+                    "alternatives": [[query, confidence] for query, confidence in alternatives],
+                    "tabId": current_tab_id,
+                }
+            }
+        )
+
+    def surf_all(phrase: Phrase):
+        """Surf to a link in any open tab"""
+        write_output({"QueryAllTabs": {"query": str(phrase), "tabId": current_tab_id}})
+
+    def surf_hints():
+        """Label the links on screen"""
+        write_output({"ShowHints": {"tabId": current_tab_id}})
+
+    def surf_label(label: str):
+        """Surf to the link with the label"""
+        write_output({"QueryLabel": {"label": label, "tabId": current_tab_id}})
+
+    def surf_forget():
+        """Forget which links were clicked on the current site"""
+        write_output({"ForgetClicks": {"tabId": current_tab_id}})
+
+    def surf_forget_all():
+        """Forget which links were clicked on every site"""
+        write_output({"ForgetAllClicks": {}})
+
+
+def update_surf(path, _):
+    # The host writes every message to the socket as well.
+    if connection is not None or Path(path).name != "v0":
+        return
+    try:
+        with (VOICESURF_PATH / "input" / "v0").open() as fp:
+            message = json.load(fp)
+    except (OSError, ValueError) as error:
+        print(f"voicesurf: ignoring input: {error}")
+        return
+    # The same message can be seen more than once.
+    if message.get("sequence") == last_sequence:
+        return
+    handle_message(message)
+
+
+def handle_message(message):
+    global current_tab_id, last_sequence, words
+    # The input file is overwritten by every message, and the socket may have connected
+    # late, so we may have missed some.
+    missed = last_sequence is None or message["sequence"] != last_sequence + 1
+    last_sequence = message["sequence"]
+    if "UpdateTalonRequest" in message:
         current_tab_id = message["UpdateTalonRequest"]["tabId"]
+        words = dict(message["UpdateTalonRequest"]["words"])
+    elif "UpdateTalonDelta" in message:
+        delta = message["UpdateTalonDelta"]
+        if missed:
+            tab_id = delta["tabId"] if current_tab_id is None else current_tab_id
+            write_output({"Resync": {"tabId": tab_id}})
+            return
+        if delta["tabId"] != current_tab_id:
+            return
+        removed = set(delta["removed"])
+        words = {spoken: written for spoken, written in words.items() if written not in removed}
+        for spoken, written in delta["added"].items():
+            # A word said as written wins, as it does on the host.
+            if words.get(spoken) != spoken:
+                words[spoken] = written
+    elif "UpdateHints" in message:
+        hints = message["UpdateHints"]
+        if hints["tabId"] == current_tab_id:
+            ctx.lists["self.surf_labels"] = {label: label for label in hints["labels"]}
+        return
 
-    ctx.lists["self.hints"] = {hint_text: str(hint_text) for hint_text in hints}
+    ctx.lists["self.hints"] = dict(words)
 
 
 @mod.capture(rule="{self.hints}+")
@@ -42,3 +228,6 @@ def hints(m) -> List[str]:
 
 
 fs.watch(str(VOICESURF_PATH / "input"), update_surf)
+connect()
+# Reconnect when the native host restarts.
+cron.interval("2s", connect)